@@ -1,23 +1,184 @@
-use std::sync::{Arc, Once};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use once_cell::sync::{Lazy, OnceCell};
 use reqwest;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
+use crate::credentials::Credentials;
 use crate::feed::Rss;
 
-const RESP_SIZE_LIMIT: usize = 2 * 1024 * 1024;
+static LIMITS: OnceCell<Limits> = OnceCell::new();
+static PROXY: OnceCell<Option<reqwest::Proxy>> = OnceCell::new();
 
-pub async fn pull_feed(url: &str) -> anyhow::Result<Rss> {
-    let mut resp = client().get(url).send().await?.error_for_status()?;
+/// Global limits of pulling feeds
+#[derive(Debug, Copy, Clone)]
+pub struct Limits {
+    pub max_size: u64,
+    pub timeout: Duration,
+    pub max_redirects: usize,
+}
+
+/// Per-feed overrides of `Limits`
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LimitsOverride {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    /// In seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_redirects: Option<usize>,
+}
+
+impl Limits {
+    fn with(&self, limits: &LimitsOverride) -> Limits {
+        Limits {
+            max_size: limits.max_size.unwrap_or(self.max_size),
+            timeout: limits
+                .timeout
+                .map(Duration::from_secs)
+                .unwrap_or(self.timeout),
+            max_redirects: limits.max_redirects.unwrap_or(self.max_redirects),
+        }
+    }
+}
+
+impl LimitsOverride {
+    pub fn is_empty(&self) -> bool {
+        *self == LimitsOverride::default()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FetchError {
+    #[error("too big ({size}, limit is {limit})")]
+    TooBig { size: Size, limit: ByteSize },
+    #[error("HTTP {status}")]
+    Status {
+        status: reqwest::StatusCode,
+        retry_after: Option<Duration>,
+    },
+}
+
+/// Size of a response, may be unknown until it's fully read
+#[derive(Debug)]
+pub enum Size {
+    Exactly(ByteSize),
+    MoreThan(ByteSize),
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Size::Exactly(size) => size.fmt(f),
+            Size::MoreThan(size) => write!(f, "more than {}", size),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ByteSize(pub u64);
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+        let ByteSize(bytes) = *self;
+        if bytes < 1024 {
+            return write!(f, "{} B", bytes);
+        }
+        let mut size = bytes as f64;
+        let mut unit = "B";
+        for u in &UNITS {
+            if size < 1024.0 {
+                break;
+            }
+            size /= 1024.0;
+            unit = u;
+        }
+        write!(f, "{:.1} {}", size, unit)
+    }
+}
+
+/// Parse a size in bytes, with an optional `K`, `M` or `G` unit, which may
+/// be followed by `B` or `iB`, e.g. `5M`, `5MB` and `5MiB`, and a bare `B`
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let upper = s.to_ascii_uppercase();
+    let (rest, binary) = if upper.ends_with("IB") {
+        (&upper[..upper.len() - 2], true)
+    } else if upper.ends_with('B') {
+        (&upper[..upper.len() - 1], false)
+    } else {
+        (&upper[..], false)
+    };
+    let (digits, shift) = match rest.chars().last() {
+        Some('K') => (&rest[..rest.len() - 1], 10),
+        Some('M') => (&rest[..rest.len() - 1], 20),
+        Some('G') => (&rest[..rest.len() - 1], 30),
+        // `iB` only follows a unit
+        Some(c) if !binary && (c.is_ascii_digit() || c.is_whitespace()) => (rest, 0),
+        _ => return Err(format!("invalid size: {}", s)),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| e.to_string())
+        .and_then(|n| {
+            n.checked_mul(1 << shift)
+                .ok_or_else(|| format!("too large: {}", s))
+        })
+}
+
+pub fn init(limits: Limits, proxy: Option<reqwest::Proxy>) {
+    LIMITS.set(limits).expect("client limits initialized twice");
+    PROXY.set(proxy).expect("client proxy initialized twice");
+}
+
+pub async fn pull_feed(
+    url: &str,
+    credentials: Option<&Credentials>,
+    limits: &LimitsOverride,
+) -> anyhow::Result<Rss> {
+    let limits = LIMITS.get().expect("LIMITS not initialized").with(limits);
+    let mut req = client(limits.max_redirects)
+        .get(url)
+        .timeout(limits.timeout);
+    for (name, value) in credentials.iter().flat_map(|c| c.headers()) {
+        req = req.header(name, value);
+    }
+    let mut resp = req.send().await?;
+    let status = resp.status();
+    if status.is_client_error() || status.is_server_error() {
+        let retry_after = resp
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        return Err(FetchError::Status {
+            status,
+            retry_after,
+        }
+        .into());
+    }
+    let too_big = |size| FetchError::TooBig {
+        size,
+        limit: ByteSize(limits.max_size),
+    };
     if let Some(len) = resp.content_length() {
-        if len > RESP_SIZE_LIMIT as u64 {
-            return Err(anyhow::format_err!("too big"));
+        if len > limits.max_size {
+            return Err(too_big(Size::Exactly(ByteSize(len))).into());
         }
     }
     let mut buf = Vec::new(); // TODO: capacity?
     while let Some(bytes) = resp.chunk().await? {
-        if buf.len() + bytes.len() > RESP_SIZE_LIMIT {
-            return Err(anyhow::format_err!("too big"));
+        if (buf.len() + bytes.len()) as u64 > limits.max_size {
+            // Without a Content-Length the actual size is unknown, and reading on
+            // to find it out would download what the limit is meant to avoid
+            return Err(too_big(Size::MoreThan(ByteSize(limits.max_size))).into());
         }
         buf.extend_from_slice(&bytes);
     }
@@ -26,11 +187,23 @@ pub async fn pull_feed(url: &str) -> anyhow::Result<Rss> {
     Ok(crate::feed::fix_relative_url(feed, url))
 }
 
-fn client() -> Arc<reqwest::Client> {
-    static mut CLIENT: Option<Arc<reqwest::Client>> = None;
-    static INIT: Once = Once::new();
+/// `Retry-After` is either seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let secs = (date.timestamp() - chrono::Utc::now().timestamp()).max(0);
+    Some(Duration::from_secs(secs as u64))
+}
+
+/// One client for each redirect limit, since the redirect policy
+/// can't be set per request
+fn client(max_redirects: usize) -> Arc<reqwest::Client> {
+    static CLIENTS: Lazy<Mutex<HashMap<usize, Arc<reqwest::Client>>>> = Lazy::new(Default::default);
 
-    INIT.call_once(|| {
+    let mut clients = CLIENTS.lock().unwrap();
+    let client = clients.entry(max_redirects).or_insert_with(|| {
         let mut headers = reqwest::header::HeaderMap::new();
         let ua = format!(
             concat!(
@@ -45,17 +218,55 @@ fn client() -> Arc<reqwest::Client> {
             reqwest::header::USER_AGENT,
             reqwest::header::HeaderValue::from_str(&ua).unwrap(),
         );
-        let client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(10))
+        let mut builder = reqwest::Client::builder()
             .default_headers(headers)
-            .redirect(reqwest::redirect::Policy::limited(5))
-            .build()
-            .unwrap();
-
-        unsafe {
-            CLIENT = Some(Arc::new(client));
+            .redirect(reqwest::redirect::Policy::limited(max_redirects));
+        if let Some(proxy) = PROXY.get().and_then(Option::as_ref) {
+            builder = builder.proxy(proxy.clone());
         }
+        let client = builder.build().unwrap();
+        Arc::new(client)
     });
+    client.clone()
+}
 
-    unsafe { CLIENT.clone() }.unwrap()
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn size_parsing() {
+        assert_eq!(parse_size("2048"), Ok(2048));
+        assert_eq!(parse_size("2K"), Ok(2048));
+        assert_eq!(parse_size("5 MiB"), Ok(5 * 1024 * 1024));
+        assert_eq!(parse_size("1g"), Ok(1024 * 1024 * 1024));
+        assert_eq!(parse_size("1B"), Ok(1));
+        assert_eq!(parse_size("3 b"), Ok(3));
+        assert_eq!(parse_size("2KB"), Ok(2048));
+        assert_eq!(parse_size("2kib"), Ok(2048));
+        for junk in &["5X", "5BI", "5MBBB", "5IB", "5iB", "B", "MB"] {
+            assert!(parse_size(junk).is_err(), "{}", junk);
+        }
+        assert!(parse_size("").is_err());
+    }
+
+    #[test]
+    fn retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::from_secs(0))
+        );
+        let later = chrono::Utc::now() + chrono::Duration::seconds(100);
+        let secs = parse_retry_after(&later.to_rfc2822()).unwrap().as_secs();
+        assert!(secs > 90 && secs <= 100);
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn size_display() {
+        assert_eq!(ByteSize(512).to_string(), "512 B");
+        assert_eq!(ByteSize(2 * 1024 * 1024).to_string(), "2.0 MiB");
+        assert_eq!(ByteSize(5_500_000).to_string(), "5.2 MiB");
+    }
 }
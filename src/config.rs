@@ -0,0 +1,430 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use once_cell::sync::OnceCell;
+use serde::{de, Deserialize, Deserializer};
+use thiserror::Error;
+
+use crate::client;
+use crate::i18n::Lang;
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Environment variable of the bot token, overrides the config file
+pub const TOKEN_ENV: &str = "RSSBOT_TOKEN";
+
+#[derive(Error, Debug)]
+#[error("invalid `{key}`: {reason}")]
+pub struct ConfigError {
+    key: &'static str,
+    reason: String,
+}
+
+fn invalid<T: fmt::Display>(key: &'static str, reason: T) -> ConfigError {
+    ConfigError {
+        key,
+        reason: reason.to_string(),
+    }
+}
+
+/// The config file, every key is optional
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub token: Option<String>,
+    pub database: PathBuf,
+    /// Telegram user IDs of the bot administrators
+    pub admins: Vec<i64>,
+    /// Language of the chats without `/lang` set, when it's unknown
+    pub lang: Lang,
+    pub fetch: Fetch,
+    pub limits: Limits,
+    pub dead_feeds: DeadFeeds,
+    pub flood: Flood,
+    pub proxy: Proxy,
+    pub log: Log,
+    pub features: Features,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fetch {
+    /// In seconds
+    pub min_interval: u32,
+    /// In seconds
+    pub max_interval: u32,
+    pub host_concurrency: u32,
+    /// In milliseconds
+    pub host_spacing: u64,
+    /// Remember the items for this long after they're gone from the feed,
+    /// so they aren't pushed again if they come back, in seconds
+    pub seen_retention: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    #[serde(deserialize_with = "size")]
+    pub max_feed_size: u64,
+    /// In seconds
+    pub timeout: u32,
+    pub max_redirects: usize,
+    /// Maximum number of feeds of the bot, unlimited if not set
+    pub max_feeds: Option<usize>,
+    /// Maximum number of subscriptions of a chat, can be overridden by `/quota`
+    pub max_subscriptions_per_chat: Option<usize>,
+    /// Maximum number of subscriptions added by a user, in all chats
+    pub max_feeds_per_user: Option<usize>,
+}
+
+/// What to do with the feeds failing for a long time
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeadFeeds {
+    /// Warn the subscribers after failing for this long, in seconds
+    pub warn_after: u32,
+    /// Warn them again after this long, in seconds, 0 to warn only once
+    pub remind_every: u32,
+    /// Unsubscribe the feed for everyone after failing for this long, in seconds
+    pub unsubscribe_after: Option<u32>,
+}
+
+/// Feeds republishing their items at once, e.g. after moving to another CMS
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Flood {
+    /// It's a flood if more than this percentage of the items are new
+    pub new_ratio: u32,
+    /// Only the feeds with at least this many items are checked
+    pub min_items: usize,
+    /// Push only this many of the newest items of a flood
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Proxy {
+    /// HTTP proxy for the Telegram Bot API
+    pub telegram: Option<String>,
+    /// Proxy for pulling feeds
+    pub feeds: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Log {
+    /// `off`, `error`, `warn`, `info`, `debug` or `trace`
+    pub level: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    /// Allow feeds needing authentication, see `/auth`
+    pub credentials: bool,
+    /// Allow subscribing for channels
+    pub channels: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            token: None,
+            database: "./rssbot.json".into(),
+            admins: Vec::new(),
+            lang: Lang::Zh,
+            fetch: Fetch::default(),
+            limits: Limits::default(),
+            dead_feeds: DeadFeeds::default(),
+            flood: Flood::default(),
+            proxy: Proxy::default(),
+            log: Log::default(),
+            features: Features::default(),
+        }
+    }
+}
+
+impl Default for Fetch {
+    fn default() -> Self {
+        Fetch {
+            min_interval: 5 * 60,
+            max_interval: 12 * 60 * 60,
+            host_concurrency: 2,
+            host_spacing: 1000,
+            seen_retention: 30 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_feed_size: 2 * 1024 * 1024,
+            timeout: 10,
+            max_redirects: 5,
+            max_feeds: None,
+            max_subscriptions_per_chat: None,
+            max_feeds_per_user: None,
+        }
+    }
+}
+
+impl Default for DeadFeeds {
+    fn default() -> Self {
+        DeadFeeds {
+            warn_after: 5 * 24 * 60 * 60,
+            remind_every: 7 * 24 * 60 * 60,
+            unsubscribe_after: None,
+        }
+    }
+}
+
+impl Default for Flood {
+    fn default() -> Self {
+        Flood {
+            new_ratio: 80,
+            min_items: 10,
+            keep: 3,
+        }
+    }
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log {
+            level: "info".into(),
+        }
+    }
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features {
+            credentials: true,
+            channels: true,
+        }
+    }
+}
+
+impl Config {
+    /// Read the config file, or the defaults if there isn't one
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Config> {
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Check the values, after merging the command line arguments
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        match &self.token {
+            None => {
+                return Err(invalid(
+                    "token",
+                    format!(
+                        "missing, set it in the arguments, ${} or the config file",
+                        TOKEN_ENV
+                    ),
+                ))
+            }
+            Some(token) if token.trim().is_empty() => return Err(invalid("token", "empty")),
+            Some(_) => (),
+        }
+        if self.fetch.min_interval < 1 {
+            return Err(invalid("fetch.min_interval", "must >= 1"));
+        }
+        if self.fetch.max_interval < self.fetch.min_interval {
+            return Err(invalid("fetch.max_interval", "must >= fetch.min_interval"));
+        }
+        if self.fetch.host_concurrency < 1 {
+            return Err(invalid("fetch.host_concurrency", "must >= 1"));
+        }
+        if self.limits.timeout < 1 {
+            return Err(invalid("limits.timeout", "must >= 1"));
+        }
+        if self.limits.max_feed_size < 1 {
+            return Err(invalid("limits.max_feed_size", "must >= 1"));
+        }
+        if self
+            .dead_feeds
+            .unsubscribe_after
+            .map_or(false, |secs| secs < self.dead_feeds.warn_after)
+        {
+            return Err(invalid(
+                "dead_feeds.unsubscribe_after",
+                "must >= dead_feeds.warn_after",
+            ));
+        }
+        if self.flood.new_ratio < 1 || self.flood.new_ratio > 100 {
+            return Err(invalid("flood.new_ratio", "must be in 1..=100"));
+        }
+        self.telegram_connector()
+            .map_err(|e| invalid("proxy.telegram", e))?;
+        self.feeds_proxy().map_err(|e| invalid("proxy.feeds", e))?;
+        self.log_level().map_err(|e| invalid("log.level", e))?;
+        Ok(())
+    }
+
+    pub fn log_level(&self) -> Result<log::LevelFilter, String> {
+        self.log
+            .level
+            .parse()
+            .map_err(|_| format!("unknown level {}", self.log.level))
+    }
+
+    /// Connector of the Bot API, through the proxy if there is one
+    pub fn telegram_connector(&self) -> Result<tbot::connectors::Proxy, String> {
+        use tbot::connectors::proxy::{Intercept, Proxy, ProxyConnector};
+
+        fn parse<T>(s: &str) -> Result<T, String>
+        where
+            T: std::str::FromStr,
+            T::Err: fmt::Display,
+        {
+            s.parse().map_err(|e: T::Err| e.to_string())
+        }
+
+        let url = match &self.proxy.telegram {
+            Some(url) => url,
+            None => return Ok(ProxyConnector::unsecured(tbot::connectors::https())),
+        };
+        let parsed = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+        if parsed.scheme() != "http" {
+            return Err("only HTTP proxies are supported".into());
+        }
+        let mut proxy = Proxy::new(Intercept::All, parse(url)?);
+        if !parsed.username().is_empty() {
+            let user_pass = format!(
+                "{}:{}",
+                parsed.username(),
+                parsed.password().unwrap_or_default()
+            );
+            let value = format!("Basic {}", base64::encode(&user_pass));
+            proxy.set_header(
+                reqwest::header::PROXY_AUTHORIZATION,
+                value
+                    .parse()
+                    .map_err(|_| "invalid credentials".to_string())?,
+            );
+        }
+        Ok(tbot::connectors::proxy(proxy))
+    }
+
+    pub fn feeds_proxy(&self) -> Result<Option<reqwest::Proxy>, String> {
+        self.proxy
+            .feeds
+            .as_ref()
+            .map(|url| reqwest::Proxy::all(url).map_err(|e| e.to_string()))
+            .transpose()
+    }
+
+    pub fn client_limits(&self) -> client::Limits {
+        client::Limits {
+            max_size: self.limits.max_feed_size,
+            timeout: std::time::Duration::from_secs(self.limits.timeout.into()),
+            max_redirects: self.limits.max_redirects,
+        }
+    }
+
+    pub fn is_admin(&self, user: i64) -> bool {
+        self.admins.contains(&user)
+    }
+}
+
+pub fn init(config: Config) {
+    CONFIG.set(config).expect("config initialized twice");
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get().expect("config not initialized")
+}
+
+/// A size is either bytes or a string like `2M`
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(n) => Ok(n),
+        Size::Text(s) => client::parse_size(&s).map_err(de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config: Config = toml::from_str(
+            r#"
+            token = "123:abc"
+            admins = [42]
+            lang = "en"
+
+            [fetch]
+            min_interval = 60
+
+            [limits]
+            max_feed_size = "5M"
+            max_feeds = 100
+
+            [features]
+            credentials = false
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.fetch.min_interval, 60);
+        assert_eq!(config.fetch.max_interval, 12 * 60 * 60);
+        assert_eq!(config.limits.max_feed_size, 5 * 1024 * 1024);
+        assert_eq!(config.limits.max_feeds, Some(100));
+        assert!(config.is_admin(42));
+        assert_eq!(config.lang, Lang::En);
+        assert!(!config.features.credentials && config.features.channels);
+    }
+
+    #[test]
+    fn errors_point_at_key() {
+        let err = toml::from_str::<Config>("[fetch]\nmin_interval = \"soon\"")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("fetch.min_interval"), "{}", err);
+        let err = toml::from_str::<Config>("[limits]\nmax_feed_size = \"5X\"")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("limits.max_feed_size"), "{}", err);
+        let err = toml::from_str::<Config>("[fetch]\nmin_intreval = 1")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("min_intreval"), "{}", err);
+
+        let mut config = Config {
+            token: Some("123:abc".into()),
+            ..Config::default()
+        };
+        config.fetch.max_interval = 1;
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("fetch.max_interval"), "{}", err);
+        config.fetch.max_interval = 600;
+        config.dead_feeds.unsubscribe_after = Some(60);
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("dead_feeds.unsubscribe_after"), "{}", err);
+        config.dead_feeds.unsubscribe_after = None;
+        config.flood.new_ratio = 0;
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("flood.new_ratio"), "{}", err);
+        config.flood.new_ratio = 50;
+        config.log.level = "loud".into();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("log.level"), "{}", err);
+    }
+}
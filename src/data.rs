@@ -1,14 +1,31 @@
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{self, File};
 use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::io::Write;
 use std::path::PathBuf;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
 use std::time::{Duration, SystemTime};
 
-use serde::{Deserialize, Serialize};
+use log::error;
+use serde::{de, Deserialize, Deserializer, Serialize};
 use serde_json;
 use thiserror::Error;
+use tokio::{
+    sync::{mpsc, Notify},
+    task, time,
+};
 
+use crate::client::LimitsOverride;
+use crate::credentials::Sealed;
 use crate::feed;
+use crate::i18n::Lang;
+use crate::messages::html_to_plain;
+
+/// Changes are saved after this delay, batched together
+const SAVE_DELAY: Duration = Duration::from_secs(1);
 
 #[derive(Error, Debug)]
 pub enum DataError {
@@ -24,6 +41,9 @@ fn gen_hash<T: Hash>(t: &T) -> u64 {
     hasher.finish()
 }
 
+/// How many update times to keep for learning the update frequency
+const UPDATE_HISTORY_LEN: usize = 16;
+
 type FeedId = u64;
 type SubscriberId = i64;
 
@@ -32,22 +52,319 @@ pub struct Feed {
     pub link: String,
     pub title: String,
     pub down_time: Option<SystemTime>,
+    /// Consecutive failures of pulling
+    #[serde(default)]
+    pub failures: u32,
+    /// When to pull it next time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_pull: Option<SystemTime>,
     pub subscribers: HashSet<SubscriberId, Size64>,
     pub ttl: Option<u32>,
+    /// When were new items found, the latest last
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub update_history: Vec<SystemTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<Sealed>,
+    /// The chat that set the credentials, only it can change them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credentials_owner: Option<SubscriberId>,
+    #[serde(default, skip_serializing_if = "LimitsOverride::is_empty")]
+    pub limits: LimitsOverride,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<SystemTime>,
+    /// Kept after the feed recovered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<PullError>,
+    /// Number of items in the feed, as of the last successful pulling
+    #[serde(default)]
+    pub item_count: usize,
+    /// When were the subscribers last warned that it's down
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warned: Option<SystemTime>,
+    /// How to tell the items apart
+    #[serde(default, skip_serializing_if = "ItemIdentity::is_auto")]
+    pub identity: ItemIdentity,
+    /// The identity changed, take the items of the next pulling as seen
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    rehash: bool,
+    /// Items seen in the feed, they are forgotten some time after they're gone
+    #[serde(default)]
+    seen: Vec<SeenItem>,
+    /// Replaced by `seen`
+    #[serde(default, skip_serializing)]
     hash_list: Vec<u64>,
 }
 
+/// How the items of a feed are told apart, the ones missing the needed
+/// fields fall back to `Auto`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemIdentity {
+    /// The GUID, or the title and the link
+    Auto,
+    Guid,
+    Link,
+    /// The link without tracking parameters and the like
+    NormalizedLink,
+    TitleLink,
+    /// The title and the content
+    Content,
+}
+
+impl ItemIdentity {
+    pub const ALL: [ItemIdentity; 6] = [
+        ItemIdentity::Auto,
+        ItemIdentity::Guid,
+        ItemIdentity::Link,
+        ItemIdentity::NormalizedLink,
+        ItemIdentity::TitleLink,
+        ItemIdentity::Content,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ItemIdentity::Auto => "auto",
+            ItemIdentity::Guid => "guid",
+            ItemIdentity::Link => "link",
+            ItemIdentity::NormalizedLink => "normalized_link",
+            ItemIdentity::TitleLink => "title_link",
+            ItemIdentity::Content => "content",
+        }
+    }
+
+    fn is_auto(&self) -> bool {
+        *self == ItemIdentity::Auto
+    }
+}
+
+impl Default for ItemIdentity {
+    fn default() -> Self {
+        ItemIdentity::Auto
+    }
+}
+
+impl std::str::FromStr for ItemIdentity {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        ItemIdentity::ALL
+            .iter()
+            .copied()
+            .find(|identity| identity.as_str() == s)
+            .ok_or(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct SeenItem {
+    hash: u64,
+    /// The last time it was in the feed
+    time: SystemTime,
+}
+
+/// At most this many seen items are kept for a feed, besides the ones in it
+const SEEN_ITEMS_LEN: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PullError {
+    pub time: SystemTime,
+    pub message: String,
+    /// HTTP status code, if the server responded with an error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Hub {
     pub callback: String,
     pub secret: String,
 }
 
+/// Settings of a chat and its subscriptions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Chat {
+    /// Overrides the max subscriptions of the chat, set by the operator
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<usize>,
+    /// Language of the messages, set by `/lang`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<Lang>,
+    /// Language of the user who subscribed the chat first, used until `/lang` sets one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detected_lang: Option<Lang>,
+    /// Items delivered in this many days aren't pushed again from other feeds, set by `/dedup`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedup_days: Option<u32>,
+    /// Items delivered in the dedup window, the oldest first
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    pub delivered: VecDeque<Delivered>,
+    /// The hashes in `delivered`, to look them up
+    #[serde(skip)]
+    delivered_hashes: HashSet<u64>,
+    /// By feed link
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub subscriptions: HashMap<String, Subscription>,
+}
+
+impl Chat {
+    fn is_empty(&self) -> bool {
+        self.quota.is_none()
+            && self.lang.is_none()
+            && self.dedup_days.is_none()
+            && self.delivered.is_empty()
+            && self.subscriptions.is_empty()
+    }
+
+    /// Forget the items delivered before the window
+    fn expire_delivered(&mut self, window: Duration, now: SystemTime) {
+        while let Some(delivered) = self.delivered.front() {
+            if now.duration_since(delivered.time).unwrap_or_default() <= window {
+                break;
+            }
+            self.delivered_hashes.remove(&delivered.hash);
+            self.delivered.pop_front();
+        }
+    }
+
+    fn remember_delivered(&mut self, hash: u64, now: SystemTime) {
+        if !self.delivered_hashes.insert(hash) {
+            return;
+        }
+        if self.delivered.len() >= DELIVERED_HISTORY_LEN {
+            if let Some(oldest) = self.delivered.pop_front() {
+                self.delivered_hashes.remove(&oldest.hash);
+            }
+        }
+        self.delivered.push_back(Delivered { hash, time: now });
+    }
+}
+
+/// An item delivered to a chat, by its GUID or normalized link
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Delivered {
+    pub hash: u64,
+    pub time: SystemTime,
+}
+
+/// How many delivered items to remember for each chat
+const DELIVERED_HISTORY_LEN: usize = 2048;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Subscription {
+    /// The user who subscribed it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_by: Option<i64>,
+    /// Items pushed for an update at most, the rest are summarized in a line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_items: Option<usize>,
+    /// Groups the feed in `/rss` and in the exported OPML, set by `/tag`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+/// A message waiting in the outbox
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Outgoing {
+    pub id: u64,
+    pub chat: SubscriberId,
+    /// In HTML, unless `plain`
+    pub text: String,
+    /// Sent as plain text, after Telegram rejected the HTML
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub plain: bool,
+    /// Failed attempts
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<SystemTime>,
+    /// Identities of the feed items in the message, remembered as delivered
+    /// once it's sent, for the chats with dedup
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<u64>,
+}
+
+/// A feed unsubscribed for everyone after failing for too long
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemovedFeed {
+    pub link: String,
+    pub title: String,
+    pub subscribers: Vec<SubscriberId>,
+    pub down_time: Option<SystemTime>,
+    pub removed_at: SystemTime,
+    pub last_error: Option<PullError>,
+}
+
+/// How many removed feeds to remember
+const REMOVED_FEEDS_LEN: usize = 256;
+
+/// The database file, it used to be a list of feeds
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Feeds(Vec<Feed>),
+    Full {
+        feeds: Vec<Feed>,
+        #[serde(default)]
+        outbox: VecDeque<Outgoing>,
+        #[serde(default, deserialize_with = "chats_by_id")]
+        chats: HashMap<SubscriberId, Chat>,
+        #[serde(default)]
+        removed: VecDeque<RemovedFeed>,
+    },
+}
+
+/// The untagged enum buffers the map keys as strings, which can't be read as integers directly
+fn chats_by_id<'de, D>(deserializer: D) -> Result<HashMap<SubscriberId, Chat>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    HashMap::<String, Chat>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(id, chat)| Ok((id.parse().map_err(de::Error::custom)?, chat)))
+        .collect()
+}
+
+#[derive(Serialize)]
+struct StoredRef<'a> {
+    feeds: Vec<&'a Feed>,
+    outbox: &'a VecDeque<Outgoing>,
+    chats: &'a HashMap<SubscriberId, Chat>,
+    removed: &'a VecDeque<RemovedFeed>,
+}
+
+/// Overview of the bot, for the administrators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub feeds: usize,
+    pub subscribers: usize,
+    pub subscriptions: usize,
+    /// Feeds failed to pull last time
+    pub failing: usize,
+    /// Messages waiting to be sent
+    pub queued: usize,
+}
+
 #[derive(Debug)]
 pub struct Database {
     path: PathBuf,
     feeds: HashMap<FeedId, Feed, Size64>,
     subscribers: HashMap<SubscriberId, HashSet<FeedId, Size64>, Size64>,
+    chats: HashMap<SubscriberId, Chat>,
+    removed: VecDeque<RemovedFeed>,
+    outbox: VecDeque<Outgoing>,
+    next_outgoing_id: u64,
+    outbox_wakeup: Arc<Notify>,
+    watcher: Option<mpsc::UnboundedSender<FeedEvent>>,
+    saving: Arc<Notify>,
+    snapshots: AtomicU64,
+    written: Arc<Mutex<u64>>,
+}
+
+/// Feeds added to or removed from database
+#[derive(Debug)]
+pub enum FeedEvent {
+    Added(String),
+    Removed(String),
 }
 
 impl Database {
@@ -56,6 +373,15 @@ impl Database {
             path,
             feeds: HashMap::with_hasher(Size64::default()),
             subscribers: HashMap::with_hasher(Size64::default()),
+            chats: HashMap::new(),
+            removed: VecDeque::new(),
+            outbox: VecDeque::new(),
+            next_outgoing_id: 0,
+            outbox_wakeup: Arc::new(Notify::new()),
+            watcher: None,
+            saving: Arc::new(Notify::new()),
+            snapshots: AtomicU64::new(0),
+            written: Arc::new(Mutex::new(0)),
         };
 
         result.save()?;
@@ -66,12 +392,32 @@ impl Database {
     pub fn open(path: PathBuf) -> Result<Database, DataError> {
         if path.exists() {
             let f = File::open(&path)?;
-            let feeds_list: Vec<Feed> = serde_json::from_reader(&f)?;
+            let (feeds_list, outbox, mut chats, removed) = match serde_json::from_reader(&f)? {
+                Stored::Feeds(feeds) => (feeds, VecDeque::new(), HashMap::new(), VecDeque::new()),
+                Stored::Full {
+                    feeds,
+                    outbox,
+                    chats,
+                    removed,
+                } => (feeds, outbox, chats, removed),
+            };
+            let next_outgoing_id = outbox.iter().map(|msg| msg.id + 1).max().unwrap_or(0);
+            for chat in chats.values_mut() {
+                chat.delivered_hashes = chat.delivered.iter().map(|d| d.hash).collect();
+            }
 
             let mut feeds = HashMap::with_capacity_and_hasher(feeds_list.len(), Size64::default());
             let mut subscribers = HashMap::with_hasher(Size64::default());
 
-            for feed in feeds_list {
+            let now = SystemTime::now();
+            for mut feed in feeds_list {
+                if feed.seen.is_empty() {
+                    let hash_list = std::mem::take(&mut feed.hash_list);
+                    feed.seen = hash_list
+                        .into_iter()
+                        .map(|hash| SeenItem { hash, time: now })
+                        .collect();
+                }
                 let feed_id = gen_hash(&feed.link);
                 for subscriber in &feed.subscribers {
                     let subscribed_feeds = subscribers
@@ -86,12 +432,34 @@ impl Database {
                 path,
                 feeds,
                 subscribers,
+                chats,
+                removed,
+                outbox,
+                next_outgoing_id,
+                outbox_wakeup: Arc::new(Notify::new()),
+                watcher: None,
+                saving: Arc::new(Notify::new()),
+                snapshots: AtomicU64::new(0),
+                written: Arc::new(Mutex::new(0)),
             })
         } else {
             Database::create(path)
         }
     }
 
+    /// Receive `FeedEvent`s, replaces the previous watcher
+    pub fn watch(&mut self) -> mpsc::UnboundedReceiver<FeedEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.watcher = Some(sender);
+        receiver
+    }
+
+    fn notify(&self, event: FeedEvent) {
+        if let Some(watcher) = &self.watcher {
+            let _ = watcher.send(event);
+        }
+    }
+
     pub fn all_feeds(&self) -> Vec<Feed> {
         self.feeds.iter().map(|(_, v)| v.clone()).collect()
     }
@@ -110,6 +478,125 @@ impl Database {
         })
     }
 
+    pub fn get_feed(&self, rss_link: &str) -> Option<Feed> {
+        self.feeds.get(&gen_hash(&rss_link)).cloned()
+    }
+
+    pub fn has_feed(&self, rss_link: &str) -> bool {
+        self.feeds.contains_key(&gen_hash(&rss_link))
+    }
+
+    pub fn feed_count(&self) -> usize {
+        self.feeds.len()
+    }
+
+    pub fn subscription_count(&self, subscriber: SubscriberId) -> usize {
+        self.subscribers
+            .get(&subscriber)
+            .map(|feeds| feeds.len())
+            .unwrap_or_default()
+    }
+
+    /// Subscriptions added by the user, in any chat
+    pub fn subscriptions_added_by(&self, user: i64) -> usize {
+        self.chats
+            .values()
+            .flat_map(|chat| chat.subscriptions.values())
+            .filter(|subscription| subscription.added_by == Some(user))
+            .count()
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            feeds: self.feeds.len(),
+            subscribers: self.subscribers.len(),
+            subscriptions: self.subscribers.values().map(|feeds| feeds.len()).sum(),
+            failing: self.feeds.values().filter(|feed| feed.failures > 0).count(),
+            queued: self.outbox.len(),
+        }
+    }
+
+    pub fn chat(&self, id: SubscriberId) -> Option<&Chat> {
+        self.chats.get(&id)
+    }
+
+    /// Set or clear the quota override of the chat
+    pub fn set_quota(&mut self, id: SubscriberId, quota: Option<usize>) {
+        self.chats.entry(id).or_default().quota = quota;
+        self.prune_chat(id);
+        self.save_later();
+    }
+
+    /// Set or clear the language of the chat
+    pub fn set_lang(&mut self, id: SubscriberId, lang: Option<Lang>) {
+        self.chats.entry(id).or_default().lang = lang;
+        self.prune_chat(id);
+        self.save_later();
+    }
+
+    /// Remember the language of a user who subscribed the chat, unless one is already known
+    pub fn detect_lang(&mut self, id: SubscriberId, lang: Lang) {
+        if let Some(chat) = self.chats.get_mut(&id) {
+            if chat.detected_lang.is_none() {
+                chat.detected_lang = Some(lang);
+                self.save_later();
+            }
+        }
+    }
+
+    /// Set the dedup window of the chat, `None` to disable it and forget the history
+    pub fn set_dedup(&mut self, id: SubscriberId, days: Option<u32>) {
+        let chat = self.chats.entry(id).or_default();
+        chat.dedup_days = days;
+        if days.is_none() {
+            chat.delivered.clear();
+            chat.delivered_hashes.clear();
+        }
+        self.prune_chat(id);
+        self.save_later();
+    }
+
+    /// The items which weren't delivered to the chat in its dedup window,
+    /// nor are waiting in the outbox for it
+    pub fn fresh_items<'a>(
+        &mut self,
+        id: SubscriberId,
+        items: &'a [feed::Item],
+    ) -> Vec<&'a feed::Item> {
+        let chat = match self.chats.get_mut(&id) {
+            Some(chat) if chat.dedup_days.is_some() => chat,
+            _ => return items.iter().collect(),
+        };
+        let window = Duration::from_secs(u64::from(chat.dedup_days.unwrap()) * 24 * 60 * 60);
+        chat.expire_delivered(window, SystemTime::now());
+        let mut known: HashSet<u64> = self
+            .outbox
+            .iter()
+            .filter(|msg| msg.chat == id)
+            .flat_map(|msg| msg.items.iter().copied())
+            .collect();
+        let mut fresh = Vec::new();
+        for item in items {
+            let hashes = delivery_hashes(item);
+            let delivered = hashes
+                .iter()
+                .any(|hash| chat.delivered_hashes.contains(hash) || known.contains(hash));
+            if !delivered {
+                // The same item may be listed twice
+                known.extend(hashes);
+                fresh.push(item);
+            }
+        }
+        fresh
+    }
+
+    /// Drop the chat settings if there's nothing left
+    fn prune_chat(&mut self, id: SubscriberId) {
+        if self.chats.get(&id).map_or(false, Chat::is_empty) {
+            self.chats.remove(&id);
+        }
+    }
+
     pub fn get_or_update_down_time(&mut self, rss_link: &str) -> Duration {
         let feed_id = gen_hash(&rss_link);
         let feed = self.feeds.get_mut(&feed_id).unwrap();
@@ -122,10 +609,41 @@ impl Database {
         }
     }
 
+    /// Count a failure, return the number of consecutive failures
+    pub fn increase_failures(&mut self, rss_link: &str, error: PullError) -> u32 {
+        let feed_id = gen_hash(&rss_link);
+        if let Some(feed) = self.feeds.get_mut(&feed_id) {
+            feed.failures += 1;
+            feed.last_error = Some(error);
+            feed.failures
+        } else {
+            0
+        }
+    }
+
+    /// Set the next pulling time, it's saved along with the next change
+    pub fn schedule(&mut self, rss_link: &str, next_pull: SystemTime) {
+        let feed_id = gen_hash(&rss_link);
+        if let Some(feed) = self.feeds.get_mut(&feed_id) {
+            feed.next_pull = Some(next_pull);
+        }
+    }
+
+    /// Remember the subscribers were warned that the feed is down
+    pub fn set_warned(&mut self, rss_link: &str, time: SystemTime) {
+        let feed_id = gen_hash(&rss_link);
+        if let Some(feed) = self.feeds.get_mut(&feed_id) {
+            feed.warned = Some(time);
+        }
+    }
+
+    /// Reset the down time and the failures
     pub fn reset_down_time(&mut self, rss_link: &str) {
         let feed_id = gen_hash(&rss_link);
         let feed = self.feeds.get_mut(&feed_id).unwrap();
         feed.down_time = None;
+        feed.failures = 0;
+        feed.warned = None;
     }
 
     pub fn is_subscribed(&self, subscriber: SubscriberId, rss_link: &str) -> bool {
@@ -135,7 +653,13 @@ impl Database {
             .unwrap_or(false)
     }
 
-    pub fn subscribe(&mut self, subscriber: SubscriberId, rss_link: &str, rss: &feed::Rss) -> bool {
+    pub fn subscribe(
+        &mut self,
+        subscriber: SubscriberId,
+        rss_link: &str,
+        rss: &feed::Rss,
+        added_by: Option<i64>,
+    ) -> bool {
         let feed_id = gen_hash(&rss_link);
         {
             let subscribed_feeds = self
@@ -146,18 +670,54 @@ impl Database {
                 return false;
             }
         }
+        let new_feed = !self.feeds.contains_key(&feed_id);
         {
             let feed = self.feeds.entry(feed_id).or_insert_with(|| Feed {
                 link: rss_link.to_owned(),
                 title: rss.title.to_owned(),
                 down_time: None,
+                failures: 0,
+                next_pull: None,
                 ttl: rss.ttl,
-                hash_list: rss.items.iter().map(gen_item_hash).collect(),
+                update_history: Vec::new(),
+                credentials: None,
+                credentials_owner: None,
+                limits: LimitsOverride::default(),
+                // It's just pulled for subscribing
+                last_success: Some(SystemTime::now()),
+                last_error: None,
+                item_count: rss.items.len(),
+                warned: None,
+                identity: ItemIdentity::Auto,
+                rehash: false,
+                seen: rss
+                    .items
+                    .iter()
+                    .map(|item| SeenItem {
+                        hash: gen_item_hash(item, ItemIdentity::Auto),
+                        time: SystemTime::now(),
+                    })
+                    .collect(),
+                hash_list: Vec::new(),
                 subscribers: HashSet::default(),
             });
             feed.subscribers.insert(subscriber);
         }
-        self.save().unwrap_or_default();
+        self.chats
+            .entry(subscriber)
+            .or_default()
+            .subscriptions
+            .insert(
+                rss_link.to_owned(),
+                Subscription {
+                    added_by,
+                    ..Subscription::default()
+                },
+            );
+        if new_feed {
+            self.notify(FeedEvent::Added(rss_link.to_owned()));
+        }
+        self.save_later();
         true
     }
 
@@ -177,11 +737,18 @@ impl Database {
         if clear_subscriber {
             self.subscribers.remove(&subscriber);
         }
+        if let Some(chat) = self.chats.get_mut(&subscriber) {
+            chat.subscriptions.remove(rss_link);
+        }
+        self.prune_chat(subscriber);
 
         let result;
         let clear_feed;
         if let Some(feed) = self.feeds.get_mut(&feed_id) {
             if feed.subscribers.remove(&subscriber) {
+                if feed.credentials_owner == Some(subscriber) {
+                    feed.credentials_owner = None;
+                }
                 clear_feed = feed.subscribers.is_empty();
                 result = feed.clone();
             } else {
@@ -192,12 +759,244 @@ impl Database {
         };
         if clear_feed {
             self.feeds.remove(&feed_id);
+            self.notify(FeedEvent::Removed(result.link.clone()));
         }
-        self.save().unwrap_or_default();
+        self.save_later();
         Some(result)
     }
 
+    /// Unsubscribe the chat from all its feeds, keeping the chat settings,
+    /// returns the number of the feeds unsubscribed
+    pub fn unsubscribe_all(&mut self, subscriber: SubscriberId) -> usize {
+        let feeds = self.subscribed_feeds(subscriber).unwrap_or_default();
+        for feed in &feeds {
+            self.unsubscribe(subscriber, &feed.link);
+        }
+        feeds.len()
+    }
+
+    /// Subscribe `to` to the feeds of `from` with the same subscription settings,
+    /// added by `added_by`, returns the number of the feeds newly subscribed
+    pub fn copy_subscriptions(
+        &mut self,
+        from: SubscriberId,
+        to: SubscriberId,
+        added_by: Option<i64>,
+    ) -> usize {
+        let feed_ids: Vec<FeedId> = match self.subscribers.get(&from) {
+            Some(feeds) => feeds.iter().copied().collect(),
+            None => return 0,
+        };
+        let mut copied = 0;
+        for feed_id in feed_ids {
+            if !self.subscribers.entry(to).or_default().insert(feed_id) {
+                continue;
+            }
+            let feed = self.feeds.get_mut(&feed_id).unwrap();
+            feed.subscribers.insert(to);
+            let link = feed.link.clone();
+            let subscription = self
+                .chats
+                .get(&from)
+                .and_then(|chat| chat.subscriptions.get(&link))
+                .cloned()
+                .unwrap_or_default();
+            let subscription = Subscription {
+                added_by,
+                ..subscription
+            };
+            self.chats
+                .entry(to)
+                .or_default()
+                .subscriptions
+                .insert(link, subscription);
+            copied += 1;
+        }
+        self.save_later();
+        copied
+    }
+
+    /// Move the subscriptions of `from` to `to`, the ones `to` already has are kept
+    /// as they are, returns the number of the feeds newly subscribed by `to`
+    pub fn transfer_subscriptions(
+        &mut self,
+        from: SubscriberId,
+        to: SubscriberId,
+        added_by: Option<i64>,
+    ) -> usize {
+        let copied = self.copy_subscriptions(from, to, added_by);
+        for feed in self.feeds.values_mut() {
+            if feed.credentials_owner == Some(from) {
+                feed.credentials_owner = Some(to);
+            }
+        }
+        self.unsubscribe_all(from);
+        copied
+    }
+
+    /// Unsubscribe a dead feed for everyone, and remember it in the database
+    pub fn remove_dead_feed(&mut self, rss_link: &str) -> Option<Feed> {
+        let feed = self.get_feed(rss_link)?;
+        for &subscriber in &feed.subscribers {
+            self.unsubscribe(subscriber, rss_link);
+        }
+        if self.removed.len() >= REMOVED_FEEDS_LEN {
+            self.removed.pop_front();
+        }
+        let mut subscribers: Vec<_> = feed.subscribers.iter().copied().collect();
+        subscribers.sort();
+        self.removed.push_back(RemovedFeed {
+            link: feed.link.clone(),
+            title: feed.title.clone(),
+            subscribers,
+            down_time: feed.down_time,
+            removed_at: SystemTime::now(),
+            last_error: feed.last_error.clone(),
+        });
+        self.save_later();
+        Some(feed)
+    }
+
+    /// The last time the feed was removed for being dead
+    pub fn removed_feed(&self, rss_link: &str) -> Option<&RemovedFeed> {
+        self.removed
+            .iter()
+            .rev()
+            .find(|removed| removed.link == rss_link)
+    }
+
+    /// Whether chats other than the subscriber subscribe to the feed
+    pub fn has_other_subscribers(&self, subscriber: SubscriberId, rss_link: &str) -> bool {
+        self.feeds.get(&gen_hash(&rss_link)).map_or(false, |feed| {
+            feed.subscribers.iter().any(|&id| id != subscriber)
+        })
+    }
+
+    /// Whether the chat may replace the credentials of the feed: the chat that
+    /// set them may, the others only when there are none. Credentials saved by
+    /// older versions have no owner, they are left to the only subscriber
+    pub fn may_set_credentials(&self, subscriber: SubscriberId, rss_link: &str) -> bool {
+        let feed = match self.feeds.get(&gen_hash(&rss_link)) {
+            Some(feed) if feed.credentials.is_some() => feed,
+            _ => return true,
+        };
+        match feed.credentials_owner {
+            Some(owner) => owner == subscriber,
+            None => feed.subscribers.len() == 1 && feed.subscribers.contains(&subscriber),
+        }
+    }
+
+    /// Replace the credentials used to pull the feed on behalf of the chat,
+    /// `None` to remove them, see `may_set_credentials`
+    pub fn set_credentials(
+        &mut self,
+        subscriber: SubscriberId,
+        rss_link: &str,
+        credentials: Option<Sealed>,
+    ) -> bool {
+        if !self.may_set_credentials(subscriber, rss_link) {
+            return false;
+        }
+        let feed_id = gen_hash(&rss_link);
+        if let Some(feed) = self.feeds.get_mut(&feed_id) {
+            feed.credentials_owner = credentials.as_ref().map(|_| subscriber);
+            feed.credentials = credentials;
+            self.save_later();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Limit the items pushed to the chat for an update of the feed
+    pub fn set_max_items(
+        &mut self,
+        subscriber: SubscriberId,
+        rss_link: &str,
+        max_items: Option<usize>,
+    ) -> bool {
+        if !self.is_subscribed(subscriber, rss_link) {
+            return false;
+        }
+        // Subscriptions from older versions may have no settings yet
+        self.chats
+            .entry(subscriber)
+            .or_default()
+            .subscriptions
+            .entry(rss_link.to_owned())
+            .or_default()
+            .max_items = max_items;
+        self.save_later();
+        true
+    }
+
+    /// Set the tag grouping the feed in `/rss` and the exported OPML, `None` to
+    /// clear it, returns false if the chat isn't subscribed to the feed
+    pub fn set_tag(
+        &mut self,
+        subscriber: SubscriberId,
+        rss_link: &str,
+        tag: Option<String>,
+    ) -> bool {
+        if !self.is_subscribed(subscriber, rss_link) {
+            return false;
+        }
+        self.chats
+            .entry(subscriber)
+            .or_default()
+            .subscriptions
+            .entry(rss_link.to_owned())
+            .or_default()
+            .tag = tag;
+        self.save_later();
+        true
+    }
+
+    /// The tag of each subscribed feed having one, by feed link
+    pub fn tags(&self, subscriber: SubscriberId) -> HashMap<String, String> {
+        self.chats
+            .get(&subscriber)
+            .map(|chat| {
+                chat.subscriptions
+                    .iter()
+                    .filter_map(|(link, subscription)| {
+                        subscription.tag.clone().map(|tag| (link.clone(), tag))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Change how the items are told apart, without pushing the items
+    /// looking new after the change
+    pub fn set_identity(&mut self, rss_link: &str, identity: ItemIdentity) -> bool {
+        let feed_id = gen_hash(&rss_link);
+        if let Some(feed) = self.feeds.get_mut(&feed_id) {
+            if feed.identity != identity {
+                feed.identity = identity;
+                feed.rehash = true;
+                self.save_later();
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn set_limits(&mut self, rss_link: &str, limits: LimitsOverride) -> bool {
+        let feed_id = gen_hash(&rss_link);
+        if let Some(feed) = self.feeds.get_mut(&feed_id) {
+            feed.limits = limits;
+            self.save_later();
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn delete_subscriber(&mut self, subscriber: SubscriberId) {
+        self.outbox.retain(|msg| msg.chat != subscriber);
+        self.chats.remove(&subscriber);
         self.subscribed_feeds(subscriber)
             .map(|feeds| {
                 for feed in feeds {
@@ -208,17 +1007,118 @@ impl Database {
     }
 
     pub fn update_subscriber(&mut self, from: SubscriberId, to: SubscriberId) {
-        let feeds = self.subscribers.remove(&from).unwrap();
-        for feed_id in &feeds {
-            let feed = self.feeds.get_mut(&feed_id).unwrap();
-            feed.subscribers.remove(&from);
-            feed.subscribers.insert(to);
+        for msg in self.outbox.iter_mut().filter(|msg| msg.chat == from) {
+            msg.chat = to;
+        }
+        if let Some(chat) = self.chats.remove(&from) {
+            self.chats.insert(to, chat);
+        }
+        if let Some(feeds) = self.subscribers.remove(&from) {
+            for feed_id in &feeds {
+                let feed = self.feeds.get_mut(&feed_id).unwrap();
+                feed.subscribers.remove(&from);
+                feed.subscribers.insert(to);
+                if feed.credentials_owner == Some(from) {
+                    feed.credentials_owner = Some(to);
+                }
+            }
+            self.subscribers.entry(to).or_default().extend(feeds);
         }
-        self.subscribers.insert(to, feeds);
+        self.save_later();
     }
 
-    /// Update the feed in database, return updates
-    pub fn update(&mut self, rss_link: &str, new_feed: feed::Rss) -> Vec<FeedUpdate> {
+    /// Queue a message to the chats, `sender` will deliver it
+    pub fn enqueue<I: IntoIterator<Item = SubscriberId>>(&mut self, chats: I, text: &str) {
+        self.enqueue_items(chats, text, &[]);
+    }
+
+    /// Queue a message with feed items, they are remembered as delivered
+    /// to the chats with dedup once it's sent
+    pub fn enqueue_items<I: IntoIterator<Item = SubscriberId>>(
+        &mut self,
+        chats: I,
+        text: &str,
+        items: &[&feed::Item],
+    ) {
+        let hashes: Vec<u64> = items
+            .iter()
+            .flat_map(|item| delivery_hashes(item))
+            .collect();
+        for chat in chats {
+            self.outbox.push_back(Outgoing {
+                id: self.next_outgoing_id,
+                chat,
+                text: text.to_owned(),
+                plain: false,
+                attempts: 0,
+                not_before: None,
+                items: hashes.clone(),
+            });
+            self.next_outgoing_id += 1;
+        }
+        self.outbox_wakeup.notify();
+        self.save_later();
+    }
+
+    pub fn outbox(&self) -> &VecDeque<Outgoing> {
+        &self.outbox
+    }
+
+    /// Notified when new messages are queued
+    pub fn outbox_wakeup(&self) -> Arc<Notify> {
+        self.outbox_wakeup.clone()
+    }
+
+    /// Remove a message from the outbox, it's saved along with the next change
+    pub fn remove_outgoing(&mut self, id: u64) {
+        self.outbox.retain(|msg| msg.id != id);
+    }
+
+    /// Remove a sent message from the outbox, and remember its items as delivered
+    pub fn sent_outgoing(&mut self, id: u64) {
+        let msg = match self.outbox.iter().position(|msg| msg.id == id) {
+            Some(i) => self.outbox.remove(i).unwrap(),
+            None => return,
+        };
+        if let Some(chat) = self.chats.get_mut(&msg.chat) {
+            if chat.dedup_days.is_some() {
+                let now = SystemTime::now();
+                for hash in msg.items {
+                    chat.remember_delivered(hash, now);
+                }
+            }
+        }
+    }
+
+    /// Send the message as plain text instead, with the links listed
+    pub fn fallback_to_plain(&mut self, id: u64) {
+        if let Some(msg) = self.outbox.iter_mut().find(|msg| msg.id == id) {
+            msg.text = html_to_plain(&msg.text);
+            msg.plain = true;
+        }
+    }
+
+    /// Count a failed attempt, and try again later
+    pub fn retry_outgoing(&mut self, id: u64, not_before: SystemTime) {
+        if let Some(msg) = self.outbox.iter_mut().find(|msg| msg.id == id) {
+            msg.attempts += 1;
+            msg.not_before = Some(not_before);
+        }
+    }
+
+    /// Mark the new items of the feed seen, and return them along with a title change
+    ///
+    /// Items gone from the feed are remembered for `retention`, so they
+    /// aren't pushed again if they come back in the meantime.
+    ///
+    /// Queue the updates before releasing the lock, the items are saved
+    /// as seen along with the queued messages.
+    pub fn update(
+        &mut self,
+        rss_link: &str,
+        new_feed: feed::Rss,
+        retention: Duration,
+    ) -> Vec<FeedUpdate> {
         let feed_id = gen_hash(&rss_link);
         if self.feeds.get(&feed_id).is_none() {
             return Vec::new();
@@ -227,66 +1127,166 @@ impl Database {
         self.reset_down_time(rss_link);
         let feed = self.feeds.get_mut(&feed_id).unwrap();
 
+        let now = SystemTime::now();
         let mut updates = Vec::new();
         let mut new_items = Vec::new();
-        let mut new_hash_list = Vec::new();
         let items_len = new_feed.items.len();
+        let mut seen: HashMap<u64, usize> = feed
+            .seen
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (item.hash, i))
+            .collect();
         for item in new_feed.items {
-            let hash = gen_item_hash(&item);
-            if !feed.hash_list.contains(&hash) {
-                new_hash_list.push(hash);
-                new_items.push(item);
+            let hash = gen_item_hash(&item, feed.identity);
+            match seen.get(&hash) {
+                Some(&i) => feed.seen[i].time = now,
+                None => {
+                    seen.insert(hash, feed.seen.len());
+                    feed.seen.push(SeenItem { hash, time: now });
+                    if !feed.rehash {
+                        new_items.push(item);
+                    }
+                }
             }
         }
+        feed.rehash = false;
+        feed.seen
+            .retain(|item| now.duration_since(item.time).unwrap_or_default() <= retention);
+        if feed.seen.len() > SEEN_ITEMS_LEN + items_len {
+            // The latest first, the items in the feed are all seen just now
+            feed.seen.sort_by_key(|item| std::cmp::Reverse(item.time));
+            feed.seen.truncate(SEEN_ITEMS_LEN + items_len);
+        }
         if !new_items.is_empty() {
             updates.push(FeedUpdate::Items(new_items));
 
-            let max_size = items_len * 2;
-            let mut append: Vec<u64> = feed
-                .hash_list
-                .iter()
-                .take(max_size - new_hash_list.len())
-                .cloned()
-                .collect();
-            new_hash_list.append(&mut append);
-            feed.hash_list = new_hash_list;
+            if feed.update_history.len() >= UPDATE_HISTORY_LEN {
+                feed.update_history.remove(0);
+            }
+            feed.update_history.push(now);
         }
         if new_feed.title != feed.title {
             updates.push(FeedUpdate::Title(new_feed.title.clone()));
             feed.title = new_feed.title;
         }
         feed.ttl = new_feed.ttl;
-        if !updates.is_empty() {
-            self.save().unwrap_or_default();
-        }
+        feed.last_success = Some(now);
+        feed.item_count = items_len;
+        self.save_later();
         updates
     }
 
+    /// Schedule a save, changes made in the meantime are saved together
+    pub fn save_later(&self) {
+        self.saving.notify();
+    }
+
+    /// Save now, blocking
     pub fn save(&self) -> Result<(), DataError> {
+        self.snapshot().write()
+    }
+
+    /// Serialize in memory, so it can be written without holding the lock
+    fn snapshot(&self) -> Snapshot {
         let feeds_list: Vec<&Feed> = self.feeds.iter().map(|(_id, feed)| feed).collect();
-        let mut file = File::create(&self.path)?;
-        if let Err(e) = serde_json::to_writer(&mut file, &feeds_list) {
-            if e.is_io() {
-                return Err(DataError::Io(e.into()));
-            } else {
-                unreachable!(e);
-            };
+        let stored = StoredRef {
+            feeds: feeds_list,
+            outbox: &self.outbox,
+            chats: &self.chats,
+            removed: &self.removed,
+        };
+        Snapshot {
+            seq: self.snapshots.fetch_add(1, Ordering::SeqCst) + 1,
+            data: serde_json::to_vec(&stored).expect("failed to serialize database"),
+            path: self.path.clone(),
+            written: self.written.clone(),
         }
+    }
+}
+
+struct Snapshot {
+    seq: u64,
+    data: Vec<u8>,
+    path: PathBuf,
+    /// `seq` of the last written snapshot
+    written: Arc<Mutex<u64>>,
+}
+
+impl Snapshot {
+    /// Snapshots can be written in any order, older ones are ignored
+    fn write(self) -> Result<(), DataError> {
+        let mut written = self.written.lock().unwrap();
+        if *written > self.seq {
+            return Ok(());
+        }
+        // Write to a temporary file first, so it's never left half written
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&self.data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        *written = self.seq;
         Ok(())
     }
 }
 
+/// Save the changes in the background, the disk writes don't block
+/// the runtime or the database lock
+pub fn start_saving(db: Arc<Mutex<Database>>) {
+    let saving = db.lock().unwrap().saving.clone();
+    tokio::spawn(async move {
+        loop {
+            saving.notified().await;
+            time::delay_for(SAVE_DELAY).await;
+            let snapshot = db.lock().unwrap().snapshot();
+            let result = task::spawn_blocking(move || snapshot.write()).await;
+            if let Err(e) = result.expect("failed to save database") {
+                error!("failed to save database: {:?}", e);
+                // Retry later
+                saving.notify();
+            }
+        }
+    });
+}
+
 pub enum FeedUpdate {
     Items(Vec<feed::Item>),
     Title(String),
 }
 
-fn gen_item_hash(item: &feed::Item) -> u64 {
-    item.id.as_ref().map(|id| gen_hash(&id)).unwrap_or_else(|| {
-        let title = item.title.as_ref().map(|s| s.as_str()).unwrap_or_default();
-        let link = item.link.as_ref().map(|s| s.as_str()).unwrap_or_default();
-        gen_hash(&format!("{}{}", title, link))
-    })
+fn gen_item_hash(item: &feed::Item, identity: ItemIdentity) -> u64 {
+    let title = item.title.as_ref().map(|s| s.as_str()).unwrap_or_default();
+    let link = item.link.as_ref().map(|s| s.as_str()).unwrap_or_default();
+    match identity {
+        ItemIdentity::Guid | ItemIdentity::Auto => {
+            if let Some(id) = &item.id {
+                return gen_hash(&id);
+            }
+        }
+        ItemIdentity::Link if !link.is_empty() => return gen_hash(&link),
+        ItemIdentity::NormalizedLink if !link.is_empty() => {
+            return gen_hash(&feed::normalize_link(link))
+        }
+        ItemIdentity::Content => {
+            if let Some(content) = &item.content {
+                return gen_hash(&(title, content));
+            }
+        }
+        _ => (),
+    }
+    gen_hash(&format!("{}{}", title, link))
+}
+
+/// Identities of the item across feeds, the GUID and the normalized link
+fn delivery_hashes(item: &feed::Item) -> Vec<u64> {
+    let id = item.id.as_ref().map(|id| gen_hash(&id));
+    let link = item
+        .link
+        .as_ref()
+        .map(|link| gen_hash(&feed::normalize_link(link)));
+    id.into_iter().chain(link).collect()
 }
 
 pub type Size64 = BuildHasherDefault<Size64Hasher>;
@@ -327,6 +1327,28 @@ impl Hasher for Size64Hasher {
 mod test {
     use super::*;
 
+    /// A database file unique to the test, removed when it's dropped
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new() -> Self {
+            static NEXT: AtomicU64 = AtomicU64::new(0);
+            let n = NEXT.fetch_add(1, Ordering::SeqCst);
+            let name = format!("rssbot-test-{}-{}.json", std::process::id(), n);
+            TempDb(std::env::temp_dir().join(name))
+        }
+
+        fn path(&self) -> PathBuf {
+            self.0.clone()
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
     #[test]
     fn size64hasher() {
         let mut h = Size64Hasher::default();
@@ -348,4 +1370,276 @@ mod test {
         let mut h = Size64Hasher::default();
         h.write_u8(0);
     }
+
+    #[test]
+    fn dedup() {
+        let temp = TempDb::new();
+        let mut db = Database::create(temp.path()).unwrap();
+        let item = |id: Option<&str>, link: &str| feed::Item {
+            title: None,
+            link: Some(link.into()),
+            id: id.map(Into::into),
+            content: None,
+        };
+        let items = vec![
+            item(None, "https://example.com/1?utm_source=a"),
+            item(Some("guid-2"), "https://example.com/2"),
+        ];
+        // Disabled
+        assert_eq!(db.fresh_items(1, &items).len(), 2);
+        assert_eq!(db.fresh_items(1, &items).len(), 2);
+
+        db.set_dedup(1, Some(3));
+        let fresh = db.fresh_items(1, &items);
+        assert_eq!(fresh.len(), 2);
+        let other_feed = vec![
+            item(None, "http://www.example.com/1/"),
+            item(Some("guid-2"), "https://mirror.example.com/2"),
+            item(None, "https://example.com/3"),
+        ];
+        // Waiting in the outbox
+        db.enqueue_items(vec![1], "", &fresh);
+        assert_eq!(db.fresh_items(1, &other_feed), vec![&other_feed[2]]);
+        // Sent
+        let id = db.outbox().back().unwrap().id;
+        db.sent_outgoing(id);
+        assert!(db.outbox().is_empty());
+        assert_eq!(db.fresh_items(1, &other_feed), vec![&other_feed[2]]);
+        // Other chats aren't affected
+        assert_eq!(db.fresh_items(2, &other_feed).len(), 3);
+
+        // Out of the window
+        let expired = SystemTime::now() - Duration::from_secs(4 * 24 * 60 * 60);
+        for delivered in &mut db.chats.get_mut(&1).unwrap().delivered {
+            delivered.time = expired;
+        }
+        assert_eq!(db.fresh_items(1, &items).len(), 2);
+
+        db.set_dedup(1, None);
+        assert!(db.chat(1).is_none());
+    }
+
+    #[test]
+    fn legacy_database() {
+        let temp = TempDb::new();
+        std::fs::write(
+            temp.path(),
+            r#"[{"link":"https://example.com/feed","title":"t","subscribers":[1,-2],"hash_list":[]}]"#,
+        )
+        .unwrap();
+        let mut db = Database::open(temp.path()).unwrap();
+        assert_eq!(db.all_subscribers().len(), 2);
+        assert!(db.outbox().is_empty());
+        db.enqueue(vec![1, -2], "<b>hello</b>");
+        db.update_subscriber(-2, -3);
+        db.fallback_to_plain(1);
+        db.delete_subscriber(1);
+        db.set_quota(-3, Some(5));
+        db.save().unwrap();
+
+        let db = Database::open(temp.path()).unwrap();
+        let outbox: Vec<_> = db.outbox().iter().map(|msg| (msg.id, msg.chat)).collect();
+        assert_eq!(outbox, vec![(1, -3)]);
+        assert!(db.outbox()[0].plain);
+        assert_eq!(db.outbox()[0].text, "hello");
+        assert_eq!(db.chat(-3).unwrap().quota, Some(5));
+    }
+
+    #[test]
+    fn subscription_owners() {
+        let temp = TempDb::new();
+        let mut db = Database::create(temp.path()).unwrap();
+        let rss = feed::Rss::default();
+        db.subscribe(1, "https://a.com/feed", &rss, Some(1));
+        db.subscribe(-2, "https://a.com/feed", &rss, Some(1));
+        db.subscribe(-2, "https://b.com/feed", &rss, Some(3));
+        assert_eq!(db.feed_count(), 2);
+        assert_eq!(db.subscription_count(-2), 2);
+        assert_eq!(db.subscriptions_added_by(1), 2);
+        let stats = db.stats();
+        assert_eq!(
+            (stats.feeds, stats.subscribers, stats.subscriptions),
+            (2, 2, 3)
+        );
+
+        db.set_quota(-2, Some(10));
+        db.unsubscribe(-2, "https://a.com/feed");
+        db.update_subscriber(-2, -4);
+        assert_eq!(db.subscriptions_added_by(1), 1);
+        assert_eq!(db.chat(-4).unwrap().quota, Some(10));
+        db.set_quota(-4, None);
+        db.delete_subscriber(-4);
+        assert!(db.chat(-4).is_none());
+        db.unsubscribe(1, "https://a.com/feed");
+        assert!(db.chats.is_empty());
+
+        db.subscribe(5, "https://a.com/feed", &rss, Some(5));
+        db.subscribe(5, "https://b.com/feed", &rss, Some(5));
+        db.set_lang(5, Some(Lang::En));
+        assert_eq!(db.unsubscribe_all(5), 2);
+        assert_eq!(db.unsubscribe_all(5), 0);
+        assert_eq!(db.feed_count(), 0);
+        assert_eq!(db.chat(5).unwrap().lang, Some(Lang::En));
+    }
+
+    #[test]
+    fn detected_lang() {
+        let temp = TempDb::new();
+        let mut db = Database::create(temp.path()).unwrap();
+        let rss = feed::Rss::default();
+
+        db.detect_lang(5, Lang::Zh);
+        assert!(db.chat(5).is_none());
+        db.subscribe(5, "https://a.com/feed", &rss, Some(5));
+        db.detect_lang(5, Lang::Zh);
+        db.detect_lang(5, Lang::En);
+        assert_eq!(db.chat(5).unwrap().detected_lang, Some(Lang::Zh));
+    }
+
+    #[test]
+    fn copy_subscriptions() {
+        let temp = TempDb::new();
+        let mut db = Database::create(temp.path()).unwrap();
+        let rss = feed::Rss::default();
+        db.subscribe(1, "https://a.com/feed", &rss, Some(1));
+        db.subscribe(1, "https://b.com/feed", &rss, Some(1));
+        db.subscribe(-2, "https://b.com/feed", &rss, Some(2));
+        db.set_max_items(1, "https://a.com/feed", Some(3));
+
+        assert_eq!(db.copy_subscriptions(1, -2, Some(5)), 1);
+        assert_eq!(db.copy_subscriptions(1, -2, Some(5)), 0);
+        assert_eq!(db.subscription_count(1), 2);
+        let subscriptions = &db.chat(-2).unwrap().subscriptions;
+        assert_eq!(subscriptions["https://a.com/feed"].max_items, Some(3));
+        assert_eq!(subscriptions["https://a.com/feed"].added_by, Some(5));
+        assert_eq!(subscriptions["https://b.com/feed"].added_by, Some(2));
+        assert!(db.set_tag(-2, "https://a.com/feed", Some("news".into())));
+        assert!(!db.set_tag(-2, "https://c.com/feed", Some("news".into())));
+
+        assert_eq!(db.transfer_subscriptions(-2, -3, Some(5)), 2);
+        assert_eq!(db.subscription_count(-2), 0);
+        assert!(db.chat(-2).is_none());
+        assert!(db
+            .get_feed("https://a.com/feed")
+            .unwrap()
+            .subscribers
+            .contains(&-3));
+        let tags = db.tags(-3);
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags["https://a.com/feed"], "news");
+        assert_eq!(db.subscriptions_added_by(5), 2);
+        assert_eq!(db.subscriptions_added_by(2), 0);
+        assert_eq!(db.copy_subscriptions(-2, -4, Some(5)), 0);
+        assert!(db.chat(-4).is_none());
+    }
+
+    #[test]
+    fn credentials_owner() {
+        let temp = TempDb::new();
+        let mut db = Database::create(temp.path()).unwrap();
+        let link = "https://a.com/feed";
+        let sealed = || serde_json::from_str::<Sealed>("\"c2VjcmV0\"").unwrap();
+        db.subscribe(1, link, &feed::Rss::default(), None);
+        db.subscribe(2, link, &feed::Rss::default(), None);
+        assert!(db.set_credentials(1, link, Some(sealed())));
+        assert!(!db.may_set_credentials(2, link));
+        assert!(!db.set_credentials(2, link, None));
+        assert!(db.get_feed(link).unwrap().credentials.is_some());
+
+        // Left to the only subscriber once the owner is gone
+        db.unsubscribe(1, link);
+        assert!(db.may_set_credentials(2, link));
+        assert!(db.set_credentials(2, link, None));
+        assert!(db.may_set_credentials(3, link));
+    }
+
+    #[test]
+    fn pulling_history() {
+        let temp = TempDb::new();
+        let mut db = Database::create(temp.path()).unwrap();
+        let link = "https://a.com/feed";
+        db.subscribe(1, link, &feed::Rss::default(), None);
+        let error = PullError {
+            time: SystemTime::now(),
+            message: "HTTP 404 Not Found".into(),
+            status: Some(404),
+        };
+        assert_eq!(db.increase_failures(link, error.clone()), 1);
+        let mut rss = feed::Rss::default();
+        rss.items.push(feed::Item::default());
+        db.update(link, rss, Duration::from_secs(60));
+        db.save().unwrap();
+
+        let feed = Database::open(temp.path()).unwrap().get_feed(link).unwrap();
+        assert_eq!(feed.failures, 0);
+        assert_eq!(feed.last_error, Some(error));
+        assert!(feed.last_success.is_some());
+        assert_eq!(feed.item_count, 1);
+
+        let mut db = Database::open(temp.path()).unwrap();
+        db.remove_dead_feed(link).unwrap();
+        db.save().unwrap();
+        let db = Database::open(temp.path()).unwrap();
+        assert!(!db.has_feed(link) && db.chats.is_empty());
+        assert_eq!(db.removed_feed(link).unwrap().subscribers, vec![1]);
+    }
+
+    #[test]
+    fn item_identity() {
+        let temp = TempDb::new();
+        let mut db = Database::create(temp.path()).unwrap();
+        let link = "https://a.com/feed";
+        let item = |id: &str, link: &str| feed::Item {
+            title: Some("title".into()),
+            link: Some(link.into()),
+            id: Some(id.into()),
+            content: None,
+        };
+        let rss = |items: Vec<feed::Item>| feed::Rss {
+            items,
+            ..feed::Rss::default()
+        };
+        let new_items = |updates: Vec<FeedUpdate>| {
+            updates
+                .into_iter()
+                .map(|update| match update {
+                    FeedUpdate::Items(items) => items.len(),
+                    FeedUpdate::Title(_) => 0,
+                })
+                .sum::<usize>()
+        };
+        let retention = Duration::from_secs(60 * 60);
+        db.subscribe(1, link, &rss(vec![item("1", "https://a.com/1")]), None);
+        let rotated = rss(vec![item("2", "https://a.com/1?utm_source=x")]);
+        assert_eq!(new_items(db.update(link, rotated.clone(), retention)), 1);
+
+        // Nothing is pushed right after the identity changed
+        assert!(db.set_identity(link, ItemIdentity::NormalizedLink));
+        assert_eq!(new_items(db.update(link, rotated, retention)), 0);
+        let rotated = rss(vec![item("3", "http://a.com/1/")]);
+        assert_eq!(new_items(db.update(link, rotated, retention)), 0);
+
+        // Gone items are remembered for the retention
+        let empty = rss(Vec::new());
+        assert_eq!(new_items(db.update(link, empty.clone(), retention)), 0);
+        let back = rss(vec![item("4", "https://a.com/1")]);
+        assert_eq!(new_items(db.update(link, back.clone(), retention)), 0);
+        db.update(link, empty.clone(), retention);
+        std::thread::sleep(Duration::from_millis(10));
+        db.update(link, empty, Duration::from_millis(1));
+        assert_eq!(new_items(db.update(link, back, retention)), 1);
+    }
+
+    #[test]
+    fn older_snapshot_is_ignored() {
+        let temp = TempDb::new();
+        let mut db = Database::create(temp.path()).unwrap();
+        let old = db.snapshot();
+        db.enqueue(vec![1], "hello");
+        db.snapshot().write().unwrap();
+        old.write().unwrap();
+
+        let db = Database::open(temp.path()).unwrap();
+        assert_eq!(db.outbox().len(), 1);
+    }
 }
@@ -154,6 +154,25 @@ pub struct Rss {
     pub source: Option<String>,
     pub ttl: Option<u32>,
     pub items: Vec<Item>,
+    /// The format told by the root element, unknown for an `Rss` not parsed
+    pub format: Option<Format>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Rss,
+    Rdf,
+    Atom,
+}
+
+impl Format {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Format::Rss => "RSS",
+            Format::Rdf => "RSS 1.0 (RDF)",
+            Format::Atom => "Atom",
+        }
+    }
 }
 
 impl FromXml for Rss {
@@ -256,6 +275,8 @@ pub struct Item {
     pub title: Option<String>,
     pub link: Option<String>,
     pub id: Option<String>,
+    /// The content or the summary, in HTML
+    pub content: Option<String>,
 }
 
 impl FromXml for Item {
@@ -266,6 +287,8 @@ impl FromXml for Item {
     ) -> quick_xml::Result<Self> {
         let mut buf = bufs.pop();
         let mut item = Item::default();
+        // The content is preferred over the summary
+        let mut full = false;
         loop {
             match reader.read_event(&mut buf) {
                 Ok(XmlEvent::Empty(ref e)) => {
@@ -298,6 +321,13 @@ impl FromXml for Item {
                         "id" | "guid" => {
                             item.id = <Option<String> as FromXml>::from_xml(bufs, reader, e)?;
                         }
+                        "content" | "content:encoded" if item.content.is_none() || !full => {
+                            item.content = <Option<String> as FromXml>::from_xml(bufs, reader, e)?;
+                            full = item.content.is_some();
+                        }
+                        "description" | "summary" if item.content.is_none() => {
+                            item.content = <Option<String> as FromXml>::from_xml(bufs, reader, e)?;
+                        }
                         _ => {
                             SkipThisElement::from_xml(bufs, reader, e)?;
                         }
@@ -366,8 +396,17 @@ pub fn parse<B: std::io::BufRead>(reader: B) -> quick_xml::Result<Rss> {
         match reader.read_event(&mut buf) {
             Ok(XmlEvent::Start(ref e)) => match reader.decode(e.name())? {
                 "rss" => continue,
-                "channel" | "feed" | "rdf:RDF" => {
-                    return Rss::from_xml(&bufs, &mut reader, e);
+                name @ "channel" | name @ "feed" | name @ "rdf:RDF" => {
+                    let format = match name {
+                        "feed" => Format::Atom,
+                        "rdf:RDF" => Format::Rdf,
+                        _ => Format::Rss,
+                    };
+                    let rss = Rss::from_xml(&bufs, &mut reader, e)?;
+                    return Ok(Rss {
+                        format: Some(format),
+                        ..rss
+                    });
                 }
                 _ => {
                     SkipThisElement::from_xml(&bufs, &mut reader, e)?;
@@ -397,6 +436,51 @@ fn url_relative_to_absolute(link: &mut String, host: &str) {
     }
 }
 
+/// The link without the parts that don't tell pages apart: the scheme,
+/// `www.`, the fragment, tracking parameters and the trailing slash
+pub fn normalize_link(link: &str) -> String {
+    let link = link.trim();
+    let rest = link.find("://").map_or(link, |i| &link[i + 3..]);
+    let rest = rest.split('#').next().unwrap_or_default();
+    let (rest, query) = match rest.find('?') {
+        Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+        None => (rest, None),
+    };
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, ""),
+    };
+    let host = host.to_ascii_lowercase();
+    let mut host = host.trim_start_matches("www.");
+    for port in &[":80", ":443"] {
+        if host.ends_with(port) {
+            host = &host[..host.len() - port.len()];
+        }
+    }
+    let mut result = format!("{}{}", host, path.trim_end_matches('/'));
+    let params = query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .filter(|param| {
+            let key = param.split('=').next().unwrap_or_default();
+            !key.is_empty() && !is_tracking_param(key)
+        })
+        .collect::<Vec<_>>();
+    if !params.is_empty() {
+        result.push('?');
+        result.push_str(&params.join("&"));
+    }
+    result
+}
+
+fn is_tracking_param(key: &str) -> bool {
+    const PARAMS: &[&str] = &[
+        "fbclid", "gclid", "dclid", "msclkid", "yclid", "igshid", "mc_cid", "mc_eid", "_hsenc",
+        "_hsmi", "mkt_tok", "ref_src", "spm",
+    ];
+    key.starts_with("utm_") || PARAMS.contains(&key)
+}
+
 pub fn fix_relative_url(mut rss: Rss, rss_link: &str) -> Rss {
     lazy_static! {
         static ref HOST: Regex = Regex::new(r"^(https?://[^/]+)").unwrap();
@@ -488,13 +572,16 @@ mod test {
                         title: Some("atom_0.3.feed.entry[0].title".into()),
                         link: Some("atom_0.3.feed.entry[0].link^href".into()),
                         id: Some("atom_0.3.feed.entry[0]^id".into()),
+                        content: Some("atom_0.3.feed.entry[0].content[0]".into()),
                     },
                     Item {
                         title: Some("atom_0.3.feed.entry[1].title".into()),
                         link: Some("atom_0.3.feed.entry[1].link^href".into()),
                         id: Some("atom_0.3.feed.entry[1]^id".into()),
+                        content: Some("atom_0.3.feed.entry[1].content[0]".into()),
                     },
                 ],
+                format: Some(Format::Atom),
                 ..Rss::default()
             }
         );
@@ -515,13 +602,16 @@ mod test {
                         title: Some("atom_1.0.feed.entry[0].title".into()),
                         link: Some("http://example.com/blog/entry1_plain".into()),
                         id: Some("atom_1.0.feed.entry[0]^id".into()),
+                        content: Some("atom_1.0.feed.entry[0].content[0]".into()),
                     },
                     Item {
                         title: Some("atom_1.0.feed.entry[1].title".into()),
                         link: Some("http://example.com/blog/entry2".into()),
                         id: Some("atom_1.0.feed.entry[1]^id".into()),
+                        content: Some("atom_1.0.feed.entry[1].content[0]".into()),
                     },
                 ],
+                format: Some(Format::Atom),
                 ..Rss::default()
             }
         );
@@ -541,13 +631,16 @@ mod test {
                         title: Some("rss_0.9.item[0].title".into()),
                         link: Some("rss_0.9.item[0].link".into()),
                         id: None,
+                        content: None,
                     },
                     Item {
                         title: Some("rss_0.9.item[1].title".into()),
                         link: Some("rss_0.9.item[1].link".into()),
                         id: None,
+                        content: None,
                     },
                 ],
+                format: Some(Format::Rdf),
                 ..Rss::default()
             }
         );
@@ -567,13 +660,16 @@ mod test {
                         title: Some("rss_0.91.channel.item[0].title".into()),
                         link: Some("rss_0.91.channel.item[0].link".into()),
                         id: None,
+                        content: Some("rss_0.91.channel.item[0].description".into()),
                     },
                     Item {
                         title: Some("rss_0.91.channel.item[1].title".into()),
                         link: Some("rss_0.91.channel.item[1].link".into()),
                         id: None,
+                        content: Some("rss_0.91.channel.item[1].description".into()),
                     },
                 ],
+                format: Some(Format::Rss),
                 ..Rss::default()
             }
         );
@@ -593,13 +689,16 @@ mod test {
                         title: Some("rss_0.92.channel.item[0].title".into()),
                         link: Some("rss_0.92.channel.item[0].link".into()),
                         id: None,
+                        content: Some("rss_0.92.channel.item[0].description".into()),
                     },
                     Item {
                         title: Some("rss_0.92.channel.item[1].title".into()),
                         link: Some("rss_0.92.channel.item[1].link".into()),
                         id: None,
+                        content: Some("rss_0.92.channel.item[1].description".into()),
                     },
                 ],
+                format: Some(Format::Rss),
                 ..Rss::default()
             }
         );
@@ -619,13 +718,16 @@ mod test {
                         title: Some("rss_0.93.channel.item[0].title".into()),
                         link: Some("rss_0.93.channel.item[0].link".into()),
                         id: None,
+                        content: Some("rss_0.93.channel.item[0].description".into()),
                     },
                     Item {
                         title: Some("rss_0.93.channel.item[1].title".into()),
                         link: Some("rss_0.93.channel.item[1].link".into()),
                         id: None,
+                        content: Some("rss_0.93.channel.item[1].description".into()),
                     },
                 ],
+                format: Some(Format::Rss),
                 ..Rss::default()
             }
         );
@@ -646,13 +748,16 @@ mod test {
                         title: Some("rss_0.94.channel.item[0].title".into()),
                         link: Some("rss_0.94.channel.item[0].link".into()),
                         id: Some("rss_0.94.channel.item[0].guid".into()),
+                        content: Some("rss_0.94.channel.item[0].description".into()),
                     },
                     Item {
                         title: Some("rss_0.94.channel.item[1].title".into()),
                         link: Some("rss_0.94.channel.item[1].link".into()),
                         id: Some("rss_0.94.channel.item[1].guid".into()),
+                        content: Some("rss_0.94.channel.item[1].description".into()),
                     },
                 ],
+                format: Some(Format::Rss),
                 ..Rss::default()
             }
         );
@@ -672,13 +777,16 @@ mod test {
                         title: Some("rss_1.0.item[0].title".into()),
                         link: Some("rss_1.0.item[0].link".into()),
                         id: None,
+                        content: Some("rss_1.0.item[0].content".into()),
                     },
                     Item {
                         title: Some("rss_1.0.item[1].title".into()),
                         link: Some("rss_1.0.item[1].link".into()),
                         id: None,
+                        content: Some("rss_1.0.item[1].content".into()),
                     },
                 ],
+                format: Some(Format::Rdf),
                 ..Rss::default()
             }
         );
@@ -699,13 +807,16 @@ mod test {
                         title: Some("rss_2.0.channel.item[0].title".into()),
                         link: Some("rss_2.0.channel.item[0].link".into()),
                         id: Some("rss_2.0.channel.item[0].guid".into()),
+                        content: Some("rss_2.0.channel.item[0].content".into()),
                     },
                     Item {
                         title: Some("rss_2.0.channel.item[1].title".into()),
                         link: Some("rss_2.0.channel.item[1].link".into()),
                         id: Some("rss_2.0.channel.item[1].guid".into()),
+                        content: Some("rss_2.0.channel.item[1].content".into()),
                     },
                 ],
+                format: Some(Format::Rss),
                 ..Rss::default()
             }
         );
@@ -812,4 +923,24 @@ mod test {
         let output = parse(Cursor::new(input)).unwrap();
         assert_eq!(output.ttl, Some(42));
     }
+
+    #[test]
+    fn normalize() {
+        let links = [
+            "https://www.Example.com/post/1/?utm_source=rss&utm_medium=feed#comments",
+            "http://example.com:80/post/1",
+            "example.com/post/1?fbclid=xxx",
+        ];
+        for link in &links {
+            assert_eq!(normalize_link(link), "example.com/post/1");
+        }
+        assert_eq!(
+            normalize_link("https://example.com/?p=1&utm_campaign=x&q=2"),
+            "example.com?p=1&q=2"
+        );
+        assert_ne!(
+            normalize_link("https://example.com/post/1"),
+            normalize_link("https://example.com/post/2")
+        );
+    }
 }
@@ -1,194 +1,421 @@
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::sync::{
     atomic::{AtomicUsize, Ordering},
     Arc, Mutex,
 };
+use std::time::SystemTime;
 
 use futures::{future::FutureExt, select_biased};
-use tbot::{
-    connectors::Https,
-    types::parameters::{self, WebPagePreviewState},
-};
+use log::{info, warn};
+use rand::Rng;
+use reqwest::StatusCode;
 use tokio::{
     self,
     stream::StreamExt,
-    sync::Notify,
-    time::{self, delay_for, delay_queue::DelayQueue, Duration, Instant},
+    sync::{mpsc, Notify, Semaphore, SemaphorePermit},
+    task::JoinHandle,
+    time::{
+        self, delay_for,
+        delay_queue::{self, DelayQueue},
+        Duration, Instant,
+    },
 };
 
-use crate::client::pull_feed;
-use crate::data::{Database, Feed, FeedUpdate};
-use crate::messages::{format_large_msg, Escape};
+use crate::client::{pull_feed, FetchError};
+use crate::config::{self, DeadFeeds, Flood};
+use crate::data::{Database, Feed, FeedEvent, FeedUpdate, PullError};
+use crate::feed;
+use crate::i18n::{self, Lang};
+use crate::messages::{render_update, Escape, HumanDuration};
+use crate::shutdown::Shutdown;
 
+/// Pull feeds until shutdown, the returned handle resolves after
+/// the pullings in progress are stopped
 pub fn start(
-    bot: tbot::Bot<tbot::connectors::Https>,
     db: Arc<Mutex<Database>>,
     min_interval: u32,
     max_interval: u32,
-) {
+    hosts: HostThrottle,
+    shutdown: Shutdown,
+) -> JoinHandle<()> {
+    let hosts = Arc::new(hosts);
+    let intervals = Intervals {
+        min: min_interval,
+        max: max_interval,
+    };
     let mut queue = FetchQueue::new();
-    // TODO: Don't use interval, it can accumulate ticks
-    // replace it with delay_until
-    let mut interval = time::interval_at(Instant::now(), Duration::from_secs(min_interval as u64));
+    let (mut events, feeds) = {
+        let mut db = db.lock().unwrap();
+        (db.watch(), db.all_feeds())
+    };
+    // Spread the overdue feeds over the min interval, instead of pulling them all at once
+    let now = SystemTime::now();
+    let overdue = feeds
+        .iter()
+        .filter(|feed| feed.next_pull.map(|t| t <= now).unwrap_or(true))
+        .count();
+    let mut stagger = 0;
+    for feed in feeds {
+        let at = match feed.next_pull {
+            Some(t) if t > now => instant_of(t),
+            _ => {
+                stagger += 1;
+                Instant::now() + Duration::from_secs(min_interval as u64) * stagger / overdue as u32
+            }
+        };
+        queue.schedule(feed.link, at);
+    }
+    let (done_sender, mut done) = mpsc::unbounded_channel();
     let throttle = Throttle::new(min_interval as usize);
+    let mut stopping = shutdown.clone();
+    let pull = move |db: &Arc<Mutex<Database>>, link: String| {
+        let feed = db.lock().unwrap().get_feed(&link);
+        let db = db.clone();
+        let hosts = hosts.clone();
+        let done = done_sender.clone();
+        let opportunity = throttle.acquire();
+        let mut shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            if let Some(feed) = feed {
+                let fetch = async {
+                    opportunity.wait().await;
+                    fetch_and_push_updates(db, &hosts, intervals, feed).await;
+                };
+                // Nothing is changed until the feed is pulled, it's safe to abandon
+                select_biased! {
+                    _ = shutdown.wait().fuse() => (),
+                    _ = fetch.fuse() => (),
+                }
+            }
+            let _ = done.send(link);
+        });
+    };
     tokio::spawn(async move {
         loop {
             select_biased! {
-                feed = queue.next().fuse() => {
-                    let feed = feed.expect("unreachable");
-                    let bot = bot.clone();
-                    let db = db.clone();
-                    let opportunity = throttle.acquire();
-                    tokio::spawn(async move {
-                        opportunity.wait().await;
-                        if let Err(e) = fetch_and_push_updates(bot, db, feed).await {
-                            dbg!(e);
-                        }
-                    });
-                }
-                _ = interval.tick().fuse() => {
-                    let feeds = db.lock().unwrap().all_feeds();
-                    for feed in feeds {
-                        let feed_interval = cmp::min(
-                            feed.ttl.map(|ttl| ttl * 60).unwrap_or(min_interval),
-                            max_interval,
-                        ) as u64 - 1; // after -1, we can stagger with `interval`
-                        queue.enqueue(feed, Duration::from_secs(feed_interval));
-                    }
+                _ = stopping.wait().fuse() => break,
+                link = done.recv().fuse() => {
+                    let link: String = link.expect("unreachable");
+                    let next_pull = db.lock().unwrap().get_feed(&link).map(|feed| feed.next_pull);
+                    // `None` if it's unsubscribed during pulling
+                    let next_pull = next_pull.map(|t| instant_of(t.unwrap_or_else(SystemTime::now)));
+                    queue.finish(link, next_pull);
                 }
+                event = events.recv().fuse() => match event.expect("unreachable") {
+                    FeedEvent::Added(link) => queue.schedule(link, Instant::now()),
+                    FeedEvent::Removed(link) => queue.remove(&link),
+                },
+                link = queue.next().fuse() => pull(&db, link.expect("unreachable")),
             }
         }
-    });
+        while !queue.pulling.is_empty() {
+            let link = done.recv().await.expect("unreachable");
+            queue.finish(link, None);
+        }
+    })
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DeadFeedAction {
+    Warn,
+    Unsubscribe,
+}
+
+/// Whether `new` out of `total` items being new looks like the feed republished everything
+fn is_flood(policy: &Flood, new: usize, total: usize) -> bool {
+    total >= policy.min_items && new * 100 > total * policy.new_ratio as usize
+}
+
+/// What to do with a feed failing for `down_time`, warned last time at `warned`
+fn dead_feed_action(
+    policy: &DeadFeeds,
+    down_time: Duration,
+    warned: Option<SystemTime>,
+    now: SystemTime,
+) -> Option<DeadFeedAction> {
+    let secs = down_time.as_secs();
+    if policy
+        .unsubscribe_after
+        .map_or(false, |after| secs >= after.into())
+    {
+        return Some(DeadFeedAction::Unsubscribe);
+    }
+    if secs < policy.warn_after.into() {
+        return None;
+    }
+    let remind = match warned {
+        None => true,
+        Some(_) if policy.remind_every == 0 => false,
+        Some(t) => {
+            now.duration_since(t).unwrap_or_default().as_secs() >= policy.remind_every.into()
+        }
+    };
+    if remind {
+        Some(DeadFeedAction::Warn)
+    } else {
+        None
+    }
+}
+
+fn instant_of(t: SystemTime) -> Instant {
+    Instant::now() + t.duration_since(SystemTime::now()).unwrap_or_default()
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Intervals {
+    min: u32,
+    max: u32,
+}
+
+impl Intervals {
+    /// Interval between two pulls of a healthy feed, in seconds
+    ///
+    /// Learned from the update history, between the `ttl` (or the min interval)
+    /// and the max interval.
+    fn of(&self, feed: &Feed) -> u32 {
+        let lower = cmp::min(feed.ttl.map(|ttl| ttl * 60).unwrap_or(self.min), self.max);
+        adaptive_interval(&feed.update_history, SystemTime::now())
+            .map(|secs| cmp::min(secs, self.max as u64) as u32)
+            .map(|secs| cmp::max(secs, lower))
+            .unwrap_or(lower)
+    }
+
+    /// Exponential backoff with jitter after consecutive failures,
+    /// capped at the max interval
+    fn backoff(&self, feed: &Feed, failures: u32) -> Duration {
+        let cap = (self.of(feed) as u64)
+            .saturating_mul(1 << cmp::min(failures, 16))
+            .min(self.max as u64);
+        let secs = rand::thread_rng().gen_range(cap / 2, cap + 1);
+        Duration::from_secs(secs)
+    }
+}
+
+/// Pull about this many times between two updates
+const PULLS_PER_UPDATE: u64 = 4;
+
+/// Estimate the pull interval from the times the feed was updated, in seconds
+///
+/// Uses the median gap between updates, or the time since the last update
+/// if the feed has been quiet for longer than that.
+fn adaptive_interval(history: &[SystemTime], now: SystemTime) -> Option<u64> {
+    if history.len() < 2 {
+        return None;
+    }
+    let mut gaps: Vec<u64> = history
+        .windows(2)
+        .map(|w| w[1].duration_since(w[0]).unwrap_or_default().as_secs())
+        .collect();
+    gaps.sort();
+    let median = gaps[gaps.len() / 2];
+    let quiet = now
+        .duration_since(*history.last().unwrap())
+        .unwrap_or_default()
+        .as_secs();
+    Some(cmp::max(median, quiet) / PULLS_PER_UPDATE)
 }
 
 async fn fetch_and_push_updates(
-    bot: tbot::Bot<tbot::connectors::Https>,
     db: Arc<Mutex<Database>>,
+    hosts: &HostThrottle,
+    intervals: Intervals,
     feed: Feed,
-) -> Result<(), tbot::errors::MethodCall> {
-    let new_feed = match pull_feed(&feed.link).await {
+) {
+    let credentials = feed
+        .credentials
+        .as_ref()
+        .map(|sealed| sealed.open(&feed.link))
+        .transpose();
+    let pulled = match credentials {
+        Ok(credentials) => {
+            let host = hosts.host(&feed.link);
+            let _permit = host.acquire().await;
+            let pulled = pull_feed(&feed.link, credentials.as_ref(), &feed.limits).await;
+            match pulled.as_ref().map_err(|e| e.downcast_ref::<FetchError>()) {
+                Err(Some(FetchError::Status {
+                    status,
+                    retry_after,
+                })) if *status == StatusCode::TOO_MANY_REQUESTS || retry_after.is_some() => {
+                    host.back_off(*retry_after)
+                }
+                Ok(_) => host.recover(),
+                Err(_) => (),
+            }
+            pulled
+        }
+        Err(e) => Err(e.into()),
+    };
+    hosts.forget_idle();
+    let new_feed = match pulled {
         Ok(feed) => feed,
         Err(e) => {
-            let down_time = db.lock().unwrap().get_or_update_down_time(&feed.link);
-            // 5 days
-            if down_time.as_secs() > 5 * 24 * 60 * 60 {
-                let msg = format!(
-                    "《<a href=\"{}\">{}</a>》\
-                     已经连续 5 天拉取出错 ({}),\
-                     可能已经关闭, 请取消订阅",
-                    Escape(&feed.link),
-                    Escape(&feed.title),
-                    Escape(&e.to_string())
-                );
-                push_updates(&bot, &db, feed.subscribers, parameters::Text::html(&msg)).await?;
+            let status = match e.downcast_ref::<FetchError>() {
+                Some(FetchError::Status { status, .. }) => Some(status.as_u16()),
+                _ => None,
+            };
+            let error = PullError {
+                time: SystemTime::now(),
+                message: e.to_string(),
+                status,
+            };
+            let mut db = db.lock().unwrap();
+            let failures = db.increase_failures(&feed.link, error);
+            let next_pull = SystemTime::now() + intervals.backoff(&feed, failures);
+            db.schedule(&feed.link, next_pull);
+            let down_time = db.get_or_update_down_time(&feed.link);
+            let policy = &config::get().dead_feeds;
+            let now = SystemTime::now();
+            let notice = |lang, key| {
+                t!(
+                    lang,
+                    key,
+                    link = Escape(&feed.link),
+                    title = Escape(&feed.title),
+                    duration = HumanDuration(lang, down_time),
+                    error = Escape(&e.to_string()),
+                    left = HumanDuration(
+                        lang,
+                        Duration::from_secs(policy.unsubscribe_after.unwrap_or_default().into())
+                            .checked_sub(down_time)
+                            .unwrap_or_default()
+                    )
+                )
+            };
+            match dead_feed_action(policy, down_time, feed.warned, now) {
+                Some(DeadFeedAction::Warn) => {
+                    let key = if policy.unsubscribe_after.is_some() {
+                        "dead.warn_unsubscribe"
+                    } else {
+                        "dead.warn"
+                    };
+                    enqueue_translated(&mut db, &feed.subscribers, |lang| notice(lang, key));
+                    db.set_warned(&feed.link, now);
+                }
+                Some(DeadFeedAction::Unsubscribe) => {
+                    info!("unsubscribing dead feed {}", feed.link);
+                    enqueue_translated(&mut db, &feed.subscribers, |lang| {
+                        notice(lang, "dead.unsubscribed")
+                    });
+                    db.remove_dead_feed(&feed.link);
+                }
+                None => (),
             }
-            return Ok(());
+            db.save_later();
+            return;
         }
     };
 
-    let updates = db.lock().unwrap().update(&feed.link, new_feed);
+    let total_items = new_feed.items.len();
+    let site = if new_feed.link.is_empty() {
+        feed.link.clone()
+    } else {
+        new_feed.link.clone()
+    };
+    let mut db = db.lock().unwrap();
+    let retention = Duration::from_secs(config::get().fetch.seen_retention.into());
+    let updates = db.update(&feed.link, new_feed, retention);
+    if let Some(updated) = db.get_feed(&feed.link) {
+        // Count from the due time, so the pulling time doesn't accumulate
+        let due = feed.next_pull.unwrap_or_else(SystemTime::now);
+        let interval = Duration::from_secs(intervals.of(&updated) as u64);
+        db.schedule(&feed.link, cmp::max(due + interval, SystemTime::now()));
+    }
     for update in updates {
         match update {
-            FeedUpdate::Items(items) => {
-                let msgs =
-                    format_large_msg(format!("<b>{}</b>", Escape(&feed.title)), &items, |item| {
-                        let title = item
-                            .title
-                            .as_ref()
-                            .map(|s| s.as_str())
-                            .unwrap_or_else(|| &feed.title);
-                        let link = item
-                            .link
-                            .as_ref()
-                            .map(|s| s.as_str())
-                            .unwrap_or_else(|| &feed.link);
-                        format!("<a href=\"{}\">{}</a>", Escape(link), Escape(title))
-                    });
-                for msg in msgs {
-                    push_updates(
-                        &bot,
-                        &db,
-                        feed.subscribers.iter().copied(),
-                        parameters::Text::html(&msg),
-                    )
-                    .await?;
+            FeedUpdate::Items(mut items) => {
+                let policy = &config::get().flood;
+                if is_flood(policy, items.len(), total_items) {
+                    warn!(
+                        "{} of {} items of {} are new, pushing only {}",
+                        items.len(),
+                        total_items,
+                        feed.link,
+                        policy.keep
+                    );
+                    // Feeds list the newest items first
+                    items.truncate(policy.keep);
+                }
+                // Chats with dedup or max items set may get fewer items,
+                // along with a line telling how many are left out
+                let mut groups: Vec<(ItemsGroup, Vec<i64>)> = Vec::new();
+                for &chat in &feed.subscribers {
+                    let mut items = db.fresh_items(chat, &items);
+                    let max_items = db
+                        .chat(chat)
+                        .and_then(|settings| settings.subscriptions.get(&feed.link))
+                        .and_then(|subscription| subscription.max_items);
+                    let more = match max_items {
+                        Some(max) if items.len() > max => {
+                            let lang = i18n::chat_lang(&db, chat);
+                            let more = items.len() - max;
+                            items.truncate(max);
+                            Some(t!(lang, "more_items", count = more, link = Escape(&site)))
+                        }
+                        _ => None,
+                    };
+                    if items.is_empty() {
+                        continue;
+                    }
+                    let group = (items, more);
+                    match groups.iter_mut().find(|(other, _)| *other == group) {
+                        Some((_, chats)) => chats.push(chat),
+                        None => groups.push((group, vec![chat])),
+                    }
+                }
+                for ((items, more), chats) in groups {
+                    let msgs = render_update(&feed.title, &feed.link, &items, more);
+                    let count = msgs.len();
+                    for (i, msg) in msgs.into_iter().enumerate() {
+                        // The items are delivered once the whole update is sent
+                        let delivered: &[&feed::Item] = if i + 1 == count { &items } else { &[] };
+                        db.enqueue_items(chats.iter().copied(), &msg, delivered);
+                    }
                 }
             }
             FeedUpdate::Title(new_title) => {
-                let msg = format!(
-                    "<a href=\"{}\">{}</a> 已更名为 {}",
-                    Escape(&feed.link),
-                    Escape(&feed.title),
-                    Escape(&new_title)
-                );
-                push_updates(
-                    &bot,
-                    &db,
-                    feed.subscribers.iter().copied(),
-                    parameters::Text::html(&msg),
-                )
-                .await?;
+                enqueue_translated(&mut db, &feed.subscribers, |lang| {
+                    t!(
+                        lang,
+                        "renamed",
+                        link = Escape(&feed.link),
+                        title = Escape(&feed.title),
+                        new_title = Escape(&new_title)
+                    )
+                });
             }
         }
     }
-    Ok(())
 }
 
-async fn push_updates<I: IntoIterator<Item = i64>>(
-    bot: &tbot::Bot<Https>,
-    db: &Arc<Mutex<Database>>,
-    subscribers: I,
-    msg: parameters::Text<'_>,
-) -> Result<(), tbot::errors::MethodCall> {
-    use tbot::errors::MethodCall;
-    for mut subscriber in subscribers {
-        'retry: for _ in 0..3 {
-            match bot
-                .send_message(tbot::types::chat::Id(subscriber), msg)
-                .web_page_preview(WebPagePreviewState::Disabled)
-                .call()
-                .await
-            {
-                Err(MethodCall::RequestError { description, .. })
-                    if chat_is_unavailable(&description) =>
-                {
-                    db.lock().unwrap().delete_subscriber(subscriber);
-                }
-                Err(MethodCall::RequestError {
-                    migrate_to_chat_id: Some(new_chat_id),
-                    ..
-                }) => {
-                    db.lock()
-                        .unwrap()
-                        .update_subscriber(subscriber, new_chat_id.0);
-                    subscriber = new_chat_id.0;
-                    continue 'retry;
-                }
-                Err(MethodCall::RequestError {
-                    retry_after: Some(delay),
-                    ..
-                }) => {
-                    time::delay_for(Duration::from_secs(delay)).await;
-                    continue 'retry;
-                }
-                other => {
-                    other?;
-                }
-            }
-            break 'retry;
+/// Items pushed to some chats, with the line about the ones left out
+type ItemsGroup<'a> = (Vec<&'a feed::Item>, Option<String>);
+
+/// Queue a message for the subscribers, in the language of each chat
+fn enqueue_translated<'a, I, F>(db: &mut Database, subscribers: I, render: F)
+where
+    I: IntoIterator<Item = &'a i64>,
+    F: Fn(Lang) -> String,
+{
+    let subscribers: Vec<i64> = subscribers.into_iter().copied().collect();
+    for &lang in &Lang::ALL {
+        let chats: Vec<i64> = subscribers
+            .iter()
+            .copied()
+            .filter(|&chat| i18n::chat_lang(db, chat) == lang)
+            .collect();
+        if !chats.is_empty() {
+            db.enqueue(chats, &render(lang));
         }
     }
-    Ok(())
-}
-
-pub fn chat_is_unavailable(s: &str) -> bool {
-    s.contains("Forbidden") || s.contains("chat not found")
 }
 
+/// Due times of feeds, a feed is either waiting here or being pulled, never both
 #[derive(Default)]
 struct FetchQueue {
-    feeds: HashMap<String, Feed>,
+    scheduled: HashMap<String, delay_queue::Key>,
+    pulling: HashSet<String>,
     notifies: DelayQueue<String>,
     wakeup: Notify,
 }
@@ -198,21 +425,39 @@ impl FetchQueue {
         Self::default()
     }
 
-    fn enqueue(&mut self, feed: Feed, delay: Duration) -> bool {
-        let exists = self.feeds.contains_key(&feed.link);
-        if !exists {
-            self.notifies.insert(feed.link.clone(), delay);
-            self.feeds.insert(feed.link.clone(), feed);
-            self.wakeup.notify();
+    /// Schedule a feed, unless it's already scheduled or being pulled
+    fn schedule(&mut self, link: String, at: Instant) {
+        if self.scheduled.contains_key(&link) || self.pulling.contains(&link) {
+            return;
+        }
+        let key = self.notifies.insert_at(link.clone(), at);
+        self.scheduled.insert(link, key);
+        self.wakeup.notify();
+    }
+
+    /// The pulling is finished, schedule the next one if it's still subscribed
+    fn finish(&mut self, link: String, next: Option<Instant>) {
+        self.pulling.remove(&link);
+        if let Some(at) = next {
+            self.schedule(link, at);
         }
-        !exists
     }
 
-    async fn next(&mut self) -> Result<Feed, time::Error> {
+    /// Feeds being pulled are left to `finish`
+    fn remove(&mut self, link: &str) {
+        if let Some(key) = self.scheduled.remove(link) {
+            self.notifies.remove(&key);
+        }
+    }
+
+    /// Wait for the next due feed, it's considered being pulled until `finish`
+    async fn next(&mut self) -> Result<String, time::Error> {
         loop {
-            if let Some(feed_id) = self.notifies.next().await {
-                let feed = self.feeds.remove(feed_id?.get_ref()).unwrap();
-                break Ok(feed);
+            if let Some(link) = self.notifies.next().await {
+                let link = link?.into_inner();
+                self.scheduled.remove(&link);
+                self.pulling.insert(link.clone());
+                break Ok(link);
             } else {
                 self.wakeup.notified().await;
             }
@@ -258,3 +503,283 @@ impl Drop for Opportunity {
         self.counter.fetch_sub(1, Ordering::SeqCst);
     }
 }
+
+/// Backoff of a host that asked us to slow down without `Retry-After`,
+/// doubled on each consecutive time
+const HOST_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_HOST_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Per-host politeness, limits the concurrency and spacing of requests
+/// to the same host, and backs off hosts responded `429` or `Retry-After`
+pub struct HostThrottle {
+    concurrency: usize,
+    spacing: Duration,
+    hosts: Mutex<HashMap<String, Arc<Host>>>,
+}
+
+impl HostThrottle {
+    pub fn new(concurrency: usize, spacing: Duration) -> Self {
+        HostThrottle {
+            concurrency,
+            spacing,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn host(&self, link: &str) -> Arc<Host> {
+        let name = reqwest::Url::parse(link)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .unwrap_or_else(|| link.to_owned());
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts
+            .entry(name.clone())
+            .or_insert_with(|| {
+                Arc::new(Host {
+                    name,
+                    concurrency: self.concurrency,
+                    spacing: self.spacing,
+                    slots: Semaphore::new(self.concurrency),
+                    state: Mutex::new(HostState {
+                        next_request: Instant::now(),
+                        backoff_until: None,
+                        backoffs: 0,
+                    }),
+                })
+            })
+            .clone()
+    }
+
+    /// Forget the hosts no pull is using, once their spacing has passed and
+    /// they have recovered from any backoff, so the map doesn't grow with
+    /// every host ever pulled
+    fn forget_idle(&self) {
+        let now = Instant::now();
+        let mut hosts = self.hosts.lock().unwrap();
+        hosts.retain(|_, host| {
+            let state = host.state.lock().unwrap();
+            Arc::strong_count(host) > 1 || state.next_request > now || state.backoffs > 0
+        });
+    }
+}
+
+struct Host {
+    name: String,
+    concurrency: usize,
+    spacing: Duration,
+    slots: Semaphore,
+    state: Mutex<HostState>,
+}
+
+struct HostState {
+    next_request: Instant,
+    backoff_until: Option<Instant>,
+    /// Consecutive backoffs
+    backoffs: u32,
+}
+
+impl Host {
+    /// Wait for a free slot, the spacing and the backoff
+    async fn acquire(&self) -> SemaphorePermit<'_> {
+        let permit = self.slots.acquire().await;
+        loop {
+            let start = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+                let start = cmp::max(
+                    state.next_request,
+                    state.backoff_until.unwrap_or(state.next_request),
+                );
+                if start <= now {
+                    state.next_request = now + self.spacing;
+                    break;
+                }
+                start
+            };
+            time::delay_until(start).await;
+        }
+        permit
+    }
+
+    fn back_off(&self, retry_after: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        state.backoffs += 1;
+        let delay = retry_after
+            .unwrap_or_else(|| HOST_BACKOFF * 2u32.saturating_pow(state.backoffs - 1))
+            .min(MAX_HOST_BACKOFF);
+        let until = Instant::now() + delay;
+        if state.backoff_until.map(|t| t < until).unwrap_or(true) {
+            state.backoff_until = Some(until);
+            warn!("{}: backing off for {}s", self, delay.as_secs());
+        }
+    }
+
+    fn recover(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.backoffs > 0 {
+            state.backoffs = 0;
+            state.backoff_until = None;
+            info!("{}: recovered", self);
+        }
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let in_flight = self.concurrency - self.slots.available_permits();
+        write!(f, "host {} ({} in flight)", self.name, in_flight)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn adaptive() {
+        let now = SystemTime::now();
+        let hours_ago = |hours: u64| now - Duration::from_secs(hours * 60 * 60);
+        assert_eq!(adaptive_interval(&[], now), None);
+        assert_eq!(adaptive_interval(&[hours_ago(1)], now), None);
+
+        // daily
+        let daily: Vec<_> = (0..5).rev().map(|d| hours_ago(d * 24)).collect();
+        assert_eq!(adaptive_interval(&daily, now), Some(6 * 60 * 60));
+        // quiet for a long time
+        let quiet: Vec<_> = (10..15).rev().map(|d| hours_ago(d * 24)).collect();
+        assert_eq!(adaptive_interval(&quiet, now), Some(10 * 24 * 60 * 60 / 4));
+
+        let intervals = Intervals {
+            min: 300,
+            max: 43200,
+        };
+        let mut feed = Feed::default();
+        assert_eq!(intervals.of(&feed), 300);
+        feed.update_history = quiet;
+        assert_eq!(intervals.of(&feed), 43200);
+        feed.update_history = (0..5).map(|m| now - Duration::from_secs(m * 60)).collect();
+        feed.update_history.reverse();
+        assert_eq!(intervals.of(&feed), 300);
+        feed.ttl = Some(60);
+        assert_eq!(intervals.of(&feed), 3600);
+    }
+
+    #[test]
+    fn flood() {
+        let policy = Flood {
+            new_ratio: 80,
+            min_items: 10,
+            keep: 3,
+        };
+        assert!(is_flood(&policy, 10, 10));
+        assert!(is_flood(&policy, 9, 10));
+        assert!(!is_flood(&policy, 8, 10));
+        // Too few items to tell
+        assert!(!is_flood(&policy, 5, 5));
+    }
+
+    #[test]
+    fn dead_feeds() {
+        let policy = DeadFeeds {
+            warn_after: 100,
+            remind_every: 50,
+            unsubscribe_after: Some(300),
+        };
+        let now = SystemTime::now();
+        let secs = Duration::from_secs;
+        let action = |down, warned| dead_feed_action(&policy, secs(down), warned, now);
+        assert_eq!(action(99, None), None);
+        assert_eq!(action(100, None), Some(DeadFeedAction::Warn));
+        assert_eq!(action(120, Some(now - secs(20))), None);
+        assert_eq!(
+            action(150, Some(now - secs(50))),
+            Some(DeadFeedAction::Warn)
+        );
+        assert_eq!(action(300, Some(now)), Some(DeadFeedAction::Unsubscribe));
+
+        let policy = DeadFeeds {
+            remind_every: 0,
+            unsubscribe_after: None,
+            ..policy
+        };
+        let action = |down, warned| dead_feed_action(&policy, secs(down), warned, now);
+        assert_eq!(action(100, None), Some(DeadFeedAction::Warn));
+        assert_eq!(action(100000, Some(now - secs(99999))), None);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let intervals = Intervals {
+            min: 300,
+            max: 43200,
+        };
+        let feed = Feed::default();
+        for failures in 1..40 {
+            let cap = cmp::min(300 << cmp::min(failures, 16), 43200);
+            let delay = intervals.backoff(&feed, failures).as_secs();
+            assert!(delay >= cap / 2 && delay <= cap, "{} {}", failures, delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn one_pull_at_a_time() {
+        let mut queue = FetchQueue::new();
+        let now = Instant::now();
+        queue.schedule("a".into(), now);
+        queue.schedule("a".into(), now);
+        queue.schedule("b".into(), now + Duration::from_secs(60));
+        assert_eq!(queue.next().await.unwrap(), "a");
+        queue.schedule("a".into(), now);
+        assert!(!queue.scheduled.contains_key("a"));
+        queue.remove("a");
+        queue.remove("b");
+        assert!(queue.scheduled.is_empty());
+        queue.finish("a".into(), Some(now));
+        assert_eq!(queue.next().await.unwrap(), "a");
+        queue.finish("a".into(), None);
+        assert!(queue.pulling.is_empty() && queue.scheduled.is_empty());
+    }
+
+    #[test]
+    fn hosts_are_shared() {
+        let hosts = HostThrottle::new(2, Duration::from_secs(1));
+        let a = hosts.host("https://github.com/a/releases.atom");
+        let b = hosts.host("https://github.com/b/releases.atom");
+        let c = hosts.host("https://example.com/feed.xml");
+        assert!(Arc::ptr_eq(&a, &b));
+        assert!(!Arc::ptr_eq(&a, &c));
+    }
+
+    #[test]
+    fn host_back_off() {
+        let hosts = HostThrottle::new(2, Duration::from_secs(1));
+        let host = hosts.host("https://example.com/feed.xml");
+        let backoff = |host: &Host| {
+            let state = host.state.lock().unwrap();
+            state.backoff_until.unwrap() - Instant::now()
+        };
+        host.back_off(None);
+        assert!(backoff(&host) <= HOST_BACKOFF);
+        host.back_off(None);
+        assert!(backoff(&host) > HOST_BACKOFF);
+        host.back_off(Some(Duration::from_secs(10 * 60 * 60)));
+        assert!(backoff(&host) <= MAX_HOST_BACKOFF);
+        host.recover();
+        assert!(host.state.lock().unwrap().backoff_until.is_none());
+    }
+
+    #[test]
+    fn idle_hosts_are_forgotten() {
+        let hosts = HostThrottle::new(2, Duration::from_secs(0));
+        let a = hosts.host("https://example.com/feed.xml");
+        let b = hosts.host("https://example.org/feed.xml");
+        b.back_off(None);
+        hosts.forget_idle();
+        assert_eq!(hosts.hosts.lock().unwrap().len(), 2);
+        drop(a);
+        drop(b);
+        hosts.forget_idle();
+        let names: Vec<_> = hosts.hosts.lock().unwrap().keys().cloned().collect();
+        assert_eq!(names, vec!["example.org".to_string()]);
+    }
+}
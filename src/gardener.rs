@@ -1,5 +1,6 @@
 use std::sync::{Arc, Mutex};
 
+use log::warn;
 use tokio::{
     self,
     time::{self, Duration},
@@ -8,20 +9,20 @@ use tokio::{
 use crate::data::Database;
 use crate::BOT_ID;
 
-pub fn start_pruning(bot: tbot::Bot<tbot::connectors::Https>, db: Arc<Mutex<Database>>) {
+pub fn start_pruning(bot: tbot::Bot<tbot::connectors::Proxy>, db: Arc<Mutex<Database>>) {
     let mut interval = time::interval(Duration::from_secs(1 * 24 * 60 * 60));
     tokio::spawn(async move {
         loop {
             interval.tick().await;
             if let Err(e) = prune(&bot, &db).await {
-                dbg!(e);
+                warn!("failed to prune subscribers: {:?}", e);
             }
         }
     });
 }
 
 async fn prune(
-    bot: &tbot::Bot<tbot::connectors::Https>,
+    bot: &tbot::Bot<tbot::connectors::Proxy>,
     db: &Mutex<Database>,
 ) -> anyhow::Result<()> {
     let subscribers = db.lock().unwrap().all_subscribers();
@@ -0,0 +1,257 @@
+//! Commands for the bot administrators, commands from others are ignored silently
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use tbot::{
+    connectors::Proxy,
+    contexts::{Command, Text},
+    types::parameters,
+};
+
+use super::{is_admin, lang_of, reply_msgs, update_response, MsgTarget};
+use crate::config;
+use crate::data::Database;
+use crate::messages::{
+    format_large_msg, rendered_len, Escape, HumanDuration, TELEGRAM_MAX_MSG_LEN,
+};
+
+pub async fn stats(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Proxy>>>) -> anyhow::Result<()> {
+    if !is_admin(&cmd) {
+        return Ok(());
+    }
+    let lang = lang_of(&db, &cmd);
+    let target = &mut MsgTarget::new(cmd.chat.id, cmd.message_id);
+    let stats = db.lock().unwrap().stats();
+    let msg = t!(
+        lang,
+        "stats",
+        feeds = stats.feeds,
+        subscribers = stats.subscribers,
+        subscriptions = stats.subscriptions,
+        failing = stats.failing,
+        queued = stats.queued
+    );
+    update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+    Ok(())
+}
+
+/// Send a plain text message to every subscriber, through the outbox
+pub async fn broadcast(
+    db: Arc<Mutex<Database>>,
+    cmd: Arc<Command<Text<Proxy>>>,
+) -> anyhow::Result<()> {
+    if !is_admin(&cmd) {
+        return Ok(());
+    }
+    let lang = lang_of(&db, &cmd);
+    let target = &mut MsgTarget::new(cmd.chat.id, cmd.message_id);
+    let text = cmd.text.value.trim();
+    if text.is_empty() {
+        let msg = t!(lang, "broadcast.usage");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(());
+    }
+    let html = Escape(text).to_string();
+    if rendered_len(&html) > TELEGRAM_MAX_MSG_LEN {
+        let msg = t!(lang, "broadcast.too_long", max = TELEGRAM_MAX_MSG_LEN);
+        update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+        return Ok(());
+    }
+    let count = {
+        let mut db = db.lock().unwrap();
+        let subscribers = db.all_subscribers();
+        let count = subscribers.len();
+        db.enqueue(subscribers, &html);
+        count
+    };
+    let msg = t!(lang, "broadcast.queued", count = count);
+    update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+    Ok(())
+}
+
+/// Show the subscribers and the pulling state of a feed
+pub async fn feedinfo(
+    db: Arc<Mutex<Database>>,
+    cmd: Arc<Command<Text<Proxy>>>,
+) -> anyhow::Result<()> {
+    if !is_admin(&cmd) {
+        return Ok(());
+    }
+    let lang = lang_of(&db, &cmd);
+    let target = &mut MsgTarget::new(cmd.chat.id, cmd.message_id);
+    let args = cmd.text.value.split_whitespace().collect::<Vec<_>>();
+    let feed_url = match &*args {
+        [url] => url,
+        [..] => {
+            let msg = t!(lang, "feedinfo.usage");
+            update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+            return Ok(());
+        }
+    };
+    let msgs = {
+        let db = db.lock().unwrap();
+        db.get_feed(feed_url).map(|feed| {
+            let now = SystemTime::now();
+            let since = |time: Option<SystemTime>| {
+                let elapsed = time.and_then(|t| now.duration_since(t).ok());
+                HumanDuration(lang, elapsed.unwrap_or_default())
+            };
+            let until = |time: Option<SystemTime>| {
+                let left = time.and_then(|t| t.duration_since(now).ok());
+                HumanDuration(lang, left.unwrap_or_default())
+            };
+            let state = if feed.failures == 0 {
+                t!(lang, "feedinfo.healthy").to_string()
+            } else {
+                t!(
+                    lang,
+                    "feedinfo.failing",
+                    failures = feed.failures,
+                    duration = since(feed.down_time)
+                )
+            };
+            let credentials = if feed.credentials.is_some() {
+                t!(lang, "yes")
+            } else {
+                t!(lang, "no")
+            };
+            let head = t!(
+                lang,
+                "feedinfo.head",
+                link = Escape(&feed.link),
+                title = Escape(&feed.title),
+                state = state,
+                next_pull = until(feed.next_pull),
+                credentials = credentials,
+                count = feed.subscribers.len()
+            );
+            let mut subscribers = feed.subscribers.iter().copied().collect::<Vec<_>>();
+            subscribers.sort();
+            format_large_msg(head, &subscribers, |&subscriber| {
+                let added_by = db
+                    .chat(subscriber)
+                    .and_then(|chat| chat.subscriptions.get(&feed.link))
+                    .and_then(|subscription| subscription.added_by);
+                match added_by {
+                    Some(user) => t!(lang, "feedinfo.added_by", chat = subscriber, user = user),
+                    None => subscriber.to_string(),
+                }
+            })
+        })
+    };
+    match msgs {
+        Some(msgs) => reply_msgs(&cmd, msgs).await?,
+        None => {
+            let msg = match db.lock().unwrap().removed_feed(feed_url) {
+                Some(removed) => t!(
+                    lang,
+                    "feedinfo.removed",
+                    ago = HumanDuration(lang, removed.removed_at.elapsed().unwrap_or_default()),
+                    count = removed.subscribers.len()
+                ),
+                None => t!(lang, "feedinfo.unknown").into(),
+            };
+            update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Unsubscribe a feed for a chat
+pub async fn forceunsub(
+    db: Arc<Mutex<Database>>,
+    cmd: Arc<Command<Text<Proxy>>>,
+) -> anyhow::Result<()> {
+    if !is_admin(&cmd) {
+        return Ok(());
+    }
+    let lang = lang_of(&db, &cmd);
+    let target = &mut MsgTarget::new(cmd.chat.id, cmd.message_id);
+    let args = cmd.text.value.split_whitespace().collect::<Vec<_>>();
+    let (chat, feed_url) = match &*args {
+        [chat, url] => (chat, url),
+        [..] => {
+            let msg = t!(lang, "forceunsub.usage");
+            update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+            return Ok(());
+        }
+    };
+    let chat = match chat.parse::<i64>() {
+        Ok(chat) => chat,
+        Err(_) => {
+            let msg = t!(lang, "invalid_chat_id");
+            update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+            return Ok(());
+        }
+    };
+    let msg = if let Some(feed) = db.lock().unwrap().unsubscribe(chat, feed_url) {
+        t!(
+            lang,
+            "forceunsub.success",
+            chat = chat,
+            link = Escape(&feed.link),
+            title = Escape(&feed.title)
+        )
+    } else {
+        t!(lang, "not_subscribed").into()
+    };
+    update_response(&cmd.bot, target, parameters::Text::html(&msg)).await?;
+    Ok(())
+}
+
+/// Show or override the max subscriptions of a chat
+pub async fn quota(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Proxy>>>) -> anyhow::Result<()> {
+    if !is_admin(&cmd) {
+        return Ok(());
+    }
+    let lang = lang_of(&db, &cmd);
+    let target = &mut MsgTarget::new(cmd.chat.id, cmd.message_id);
+    let args = cmd.text.value.split_whitespace().collect::<Vec<_>>();
+    let (chat, value) = match &*args {
+        [chat] => (chat, None),
+        [chat, value] => (chat, Some(value)),
+        [..] => {
+            let msg = t!(lang, "quota.usage");
+            update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+            return Ok(());
+        }
+    };
+    let chat = match chat.parse::<i64>() {
+        Ok(chat) => chat,
+        Err(_) => {
+            let msg = t!(lang, "invalid_chat_id");
+            update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+            return Ok(());
+        }
+    };
+    match value.map(|value| (*value, value.parse::<usize>())) {
+        None => (),
+        Some(("default", _)) => db.lock().unwrap().set_quota(chat, None),
+        Some((_, Ok(n))) => db.lock().unwrap().set_quota(chat, Some(n)),
+        Some((_, Err(e))) => {
+            let msg = t!(lang, "quota.invalid", error = e);
+            update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+            return Ok(());
+        }
+    }
+    let msg = {
+        let db = db.lock().unwrap();
+        let quota = db.chat(chat).and_then(|chat| chat.quota);
+        let limit = match (quota, config::get().limits.max_subscriptions_per_chat) {
+            (Some(n), _) => t!(lang, "quota.custom", max = n),
+            (None, Some(n)) => t!(lang, "quota.default", max = n),
+            (None, None) => t!(lang, "quota.unlimited").into(),
+        };
+        t!(
+            lang,
+            "quota.show",
+            chat = chat,
+            count = db.subscription_count(chat),
+            limit = limit
+        )
+    };
+    update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+    Ok(())
+}
@@ -1,23 +1,55 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
 
 use either::Either;
+use futures::stream::{self, StreamExt};
+use log::warn;
 use pinyin::{Pinyin, ToPinyin};
 use tbot::{
-    connectors::Https,
-    contexts::{Command, Text},
+    connectors::Proxy,
+    contexts::{traits::Callback, Command, DataCallback, Text},
     types::{
+        callback::Origin,
         input_file,
+        keyboard::inline::{Button, ButtonKind, Keyboard},
         parameters::{self, WebPagePreviewState},
     },
 };
 
-use crate::client::pull_feed;
-use crate::data::Database;
-use crate::messages::{format_large_msg, Escape};
+use crate::client::{parse_size, pull_feed, ByteSize, LimitsOverride};
+use crate::config;
+use crate::credentials::{Credentials, CredentialsError};
+use crate::data::{Database, Feed, ItemIdentity};
+use crate::feed::{self, Format};
+use crate::i18n::{self, Lang};
+use crate::messages::{
+    format_large_msg, render_update, truncate_html, Escape, HumanDuration, TELEGRAM_MAX_MSG_LEN,
+};
 
+mod admin;
 mod opml;
 
+pub use admin::{broadcast, feedinfo, forceunsub, quota, stats};
+
+/// Upper bounds of the per-feed limits
+const MAX_FEED_SIZE: u64 = 64 * 1024 * 1024;
+const MAX_TIMEOUT: u64 = 5 * 60;
+const MAX_REDIRECTS: usize = 20;
+/// Number of the latest items shown by /preview
+const PREVIEW_ITEMS: usize = 5;
+/// Upper bound of the feeds given to /sub or /unsub at once
+const MAX_BULK_URLS: usize = 50;
+/// Number of the feeds pulled at once by /sub
+const BULK_CONCURRENCY: usize = 4;
+/// The least time between the edits of a progress reply
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+/// Upper bound of the characters in a tag
+const MAX_TAG_LEN: usize = 32;
+/// Upper bound of the size of an OPML file given to /import
+const MAX_OPML_SIZE: u32 = 1024 * 1024;
+
 #[derive(Debug, Copy, Clone)]
 struct MsgTarget {
     chat_id: tbot::types::chat::Id,
@@ -39,21 +71,16 @@ impl MsgTarget {
     }
 }
 
-pub async fn start(
-    _db: Arc<Mutex<Database>>,
-    cmd: Arc<Command<Text<Https>>>,
-) -> anyhow::Result<()> {
+pub async fn start(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Proxy>>>) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
     let target = &mut MsgTarget::new(cmd.chat.id, cmd.message_id);
-    let msg = "命令列表：\n\
-               /rss       - 显示当前订阅的 RSS 列表\n\
-               /sub       - 订阅一个 RSS: /sub http://example.com/feed.xml\n\
-               /unsub     - 退订一个 RSS: /unsub http://example.com/feed.xml\n\
-               /export    - 导出为 OPML";
-    update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+    let msg = t!(lang, "help");
+    update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
     Ok(())
 }
 
-pub async fn rss(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Https>>>) -> anyhow::Result<()> {
+pub async fn rss(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Proxy>>>) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
     let chat_id = cmd.chat.id;
     let channel = &cmd.text.value;
     let mut target_id = chat_id;
@@ -61,65 +88,645 @@ pub async fn rss(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Https>>>) -> an
 
     if !channel.is_empty() {
         let user_id = cmd.from.as_ref().unwrap().id;
-        let channel_id = check_channel_permission(&cmd.bot, channel, target, user_id).await?;
+        let channel_id = check_channel_permission(&cmd.bot, lang, channel, target, user_id).await?;
         if channel_id.is_none() {
             return Ok(());
         }
         target_id = channel_id.unwrap();
     }
 
-    let feeds = db.lock().unwrap().subscribed_feeds(target_id.0);
+    let (feeds, tags) = {
+        let db = db.lock().unwrap();
+        (db.subscribed_feeds(target_id.0), db.tags(target_id.0))
+    };
     let msgs = if let Some(mut feeds) = feeds {
-        feeds.sort_by_cached_key(|feed| {
-            feed.title
-                .chars()
-                .map(|c| {
-                    c.to_pinyin()
-                        .map(Pinyin::plain)
-                        .map(Either::Right)
-                        .unwrap_or_else(|| Either::Left(c))
-                })
-                .collect::<Vec<Either<char, &str>>>()
-        });
-        format_large_msg("订阅列表：".to_string(), &feeds, |feed| {
+        sort_by_title(&mut feeds);
+        let line = |feed: &Feed| {
             format!(
                 "<a href=\"{}\">{}</a>",
                 Escape(&feed.link),
                 Escape(&feed.title)
             )
+        };
+        // The feeds without a tag first, then a group per tag
+        let mut lines = Vec::with_capacity(feeds.len());
+        let mut groups: BTreeMap<&str, Vec<&Feed>> = BTreeMap::new();
+        for feed in &feeds {
+            match tags.get(&feed.link) {
+                Some(tag) => groups.entry(tag).or_default().push(feed),
+                None => lines.push(line(feed)),
+            }
+        }
+        for (tag, feeds) in groups {
+            lines.push(format!("\n<b>{}</b>", Escape(tag)));
+            lines.extend(feeds.into_iter().map(line));
+        }
+        format_large_msg(t!(lang, "rss.head").to_string(), &lines, |line| {
+            line.clone()
         })
     } else {
-        vec!["订阅列表为空".to_string()]
+        vec![t!(lang, "rss.empty").to_string()]
     };
 
-    let mut prev_msg = cmd.message_id;
-    for msg in msgs {
-        let text = parameters::Text::html(&msg);
-        let msg = cmd
-            .bot
-            .send_message(chat_id, text)
-            .reply_to_message_id(prev_msg)
-            .web_page_preview(WebPagePreviewState::Disabled)
+    reply_msgs(&cmd, msgs).await?;
+    Ok(())
+}
+
+pub async fn status(
+    db: Arc<Mutex<Database>>,
+    cmd: Arc<Command<Text<Proxy>>>,
+) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
+    let chat_id = cmd.chat.id;
+    let channel = &cmd.text.value;
+    let mut target_id = chat_id;
+    let target = &mut MsgTarget::new(chat_id, cmd.message_id);
+
+    if !channel.is_empty() {
+        let user_id = cmd.from.as_ref().unwrap().id;
+        let channel_id = check_channel_permission(&cmd.bot, lang, channel, target, user_id).await?;
+        if channel_id.is_none() {
+            return Ok(());
+        }
+        target_id = channel_id.unwrap();
+    }
+
+    let feeds = db.lock().unwrap().subscribed_feeds(target_id.0);
+    let msgs = if let Some(mut feeds) = feeds {
+        sort_by_title(&mut feeds);
+        let now = SystemTime::now();
+        let ago =
+            |time: SystemTime| HumanDuration(lang, now.duration_since(time).unwrap_or_default());
+        format_large_msg(t!(lang, "status.head").to_string(), &feeds, |feed| {
+            let mut lines = vec![format!(
+                "<a href=\"{}\">{}</a>",
+                Escape(&feed.link),
+                Escape(&feed.title)
+            )];
+            match feed.last_success {
+                Some(time) => lines.push(t!(
+                    lang,
+                    "status.last_success",
+                    ago = ago(time),
+                    count = feed.item_count
+                )),
+                None => lines.push(t!(lang, "status.never_succeeded").to_string()),
+            }
+            if let Some(error) = &feed.last_error {
+                // The message of an HTTP error already tells the status
+                let status = error
+                    .status
+                    .filter(|status| !error.message.contains(&status.to_string()))
+                    .map(|status| format!("HTTP {}, ", status))
+                    .unwrap_or_default();
+                lines.push(t!(
+                    lang,
+                    "status.last_error",
+                    ago = ago(error.time),
+                    error = format!("{}{}", status, Escape(&error.message))
+                ));
+            }
+            if feed.failures > 0 {
+                lines.push(t!(lang, "status.failures", count = feed.failures));
+            }
+            let next_pull = feed
+                .next_pull
+                .and_then(|t| t.duration_since(now).ok())
+                .unwrap_or_default();
+            lines.push(t!(
+                lang,
+                "status.next_pull",
+                left = HumanDuration(lang, next_pull)
+            ));
+            lines.join("\n")
+        })
+    } else {
+        vec![t!(lang, "rss.empty").to_string()]
+    };
+
+    reply_msgs(&cmd, msgs).await?;
+    Ok(())
+}
+
+pub async fn sub(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Proxy>>>) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
+    let chat_id = cmd.chat.id;
+    let (text, lines) = split_first_line(&cmd.text.value);
+    let (url_lines, credentials_lines) = split_credentials(lines);
+    let credentials_text = credentials_lines.join("\n");
+    let (args, options) = split_options(text);
+    let mut target_id = chat_id;
+    let target = &mut MsgTarget::new(chat_id, cmd.message_id);
+
+    let (channel, feed_urls) = feed_args(&args, &url_lines.join("\n"), reply_links(&cmd));
+    if feed_urls.is_empty() {
+        let msg = t!(lang, "sub.usage");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(());
+    }
+    if feed_urls.len() > MAX_BULK_URLS {
+        let msg = t!(lang, "bulk.too_many", max = MAX_BULK_URLS);
+        update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+        return Ok(());
+    }
+    if let Some(channel) = channel {
+        let user_id = cmd.from.as_ref().unwrap().id;
+        let channel_id = check_channel_permission(&cmd.bot, lang, channel, target, user_id).await?;
+        if channel_id.is_none() {
+            return Ok(());
+        }
+        target_id = channel_id.unwrap();
+    }
+    if !credentials_text.trim().is_empty() && !config::get().features.credentials {
+        let msg = t!(lang, "auth.disabled");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(());
+    }
+    if !credentials_text.trim().is_empty() && !cmd.chat.kind.is_private() {
+        let msg = t!(lang, "auth.private_only");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(());
+    }
+    if !credentials_text.trim().is_empty() && feed_urls.len() > 1 {
+        let msg = t!(lang, "bulk.credentials");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(());
+    }
+    let credentials = match Credentials::parse(&credentials_text) {
+        Ok(credentials) if credentials.is_empty() => None,
+        Ok(credentials) => Some(credentials),
+        Err(e) => {
+            let msg = credentials_error(lang, &e);
+            update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+            return Ok(());
+        }
+    };
+
+    let user_id = cmd.from.as_ref().map(|user| user.id.0);
+    let msg = t!(lang, "processing");
+    update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+    if let [feed_url] = &*feed_urls {
+        let msg = subscribe_feed(
+            &db,
+            lang,
+            target_id.0,
+            user_id,
+            feed_url,
+            credentials,
+            &options,
+        )
+        .await;
+        remember_lang(&db, &cmd, target_id.0);
+        update_response(&cmd.bot, target, parameters::Text::html(&msg)).await?;
+        return Ok(());
+    }
+
+    let outcomes = subscribe_bulk(&db, &cmd, lang, target, target_id.0, &feed_urls, &options).await;
+    remember_lang(&db, &cmd, target_id.0);
+    let msg = bulk_report(lang, &feed_urls, &outcomes);
+    update_response(&cmd.bot, target, parameters::Text::html(&msg)).await?;
+    Ok(())
+}
+
+/// Subscribe the chat to the feeds, reporting the progress in the reply,
+/// returns the outcome of each feed
+async fn subscribe_bulk(
+    db: &Arc<Mutex<Database>>,
+    cmd: &Command<Text<Proxy>>,
+    lang: Lang,
+    target: &mut MsgTarget,
+    target_id: i64,
+    feed_urls: &[String],
+    options: &[&str],
+) -> Vec<Option<String>> {
+    let user_id = cmd.from.as_ref().map(|user| user.id.0);
+    let mut outcomes = vec![None; feed_urls.len()];
+    let mut pulling = stream::iter(feed_urls.iter().cloned().enumerate())
+        .map(|(i, feed_url)| {
+            let db = db.clone();
+            async move {
+                let msg = subscribe_feed(&db, lang, target_id, user_id, &feed_url, None, options);
+                (i, msg.await)
+            }
+        })
+        .buffer_unordered(BULK_CONCURRENCY);
+    let mut last_edit = Instant::now();
+    let mut done = 0;
+    while let Some((i, msg)) = pulling.next().await {
+        outcomes[i] = Some(msg);
+        done += 1;
+        // Edit the reply at most once per interval, Telegram limits the rate.
+        // A failed edit mustn't stop the subscriptions still being pulled
+        if done < feed_urls.len() && last_edit.elapsed() >= PROGRESS_INTERVAL {
+            let msg = bulk_report(lang, feed_urls, &outcomes);
+            if let Err(e) = update_response(&cmd.bot, target, parameters::Text::html(&msg)).await {
+                warn!("failed to report the progress of subscribing: {:?}", e);
+            }
+            last_edit = Instant::now();
+        }
+    }
+    outcomes
+}
+
+/// The notices pushed to the chat are in the language of the user who subscribed
+/// it, since there's no user to take it from when pushing
+fn remember_lang(db: &Mutex<Database>, cmd: &Command<Text<Proxy>>, target_id: i64) {
+    if let Some(lang) = user_lang(cmd) {
+        db.lock().unwrap().detect_lang(target_id, lang);
+    }
+}
+
+/// Subscribe the chat to a feed, returns the outcome as HTML
+async fn subscribe_feed(
+    db: &Mutex<Database>,
+    lang: Lang,
+    target_id: i64,
+    user_id: Option<i64>,
+    feed_url: &str,
+    credentials: Option<Credentials>,
+    options: &[&str],
+) -> String {
+    let existing_limits = db
+        .lock()
+        .unwrap()
+        .get_feed(feed_url)
+        .map(|feed| feed.limits)
+        .unwrap_or_default();
+    let limits = match parse_limits(lang, existing_limits, options) {
+        Ok(limits) => limits,
+        Err(msg) => return Escape(&msg).to_string(),
+    };
+    if db.lock().unwrap().is_subscribed(target_id, feed_url) {
+        return t!(lang, "sub.already_subscribed").into();
+    }
+    let exceeded = check_quota(&db.lock().unwrap(), lang, target_id, user_id, feed_url);
+    if let Some(msg) = exceeded {
+        return Escape(&msg).to_string();
+    }
+    if !options.is_empty() && !may_change_feed(&db.lock().unwrap(), target_id, user_id, feed_url) {
+        return t!(lang, "set.shared").into();
+    }
+    if credentials.is_some() && !db.lock().unwrap().may_set_credentials(target_id, feed_url) {
+        return t!(lang, "auth.not_owner").into();
+    }
+    match pull_feed(feed_url, credentials.as_ref(), &limits).await {
+        Ok(feed) => {
+            let mut db = db.lock().unwrap();
+            // Check again, others may subscribed during pulling
+            if let Some(msg) = check_quota(&db, lang, target_id, user_id, feed_url) {
+                Escape(&msg).to_string()
+            } else if db.subscribe(target_id, feed_url, &feed, user_id) {
+                if let Some(credentials) = credentials {
+                    db.set_credentials(target_id, feed_url, Some(credentials.seal(feed_url)));
+                }
+                if !options.is_empty() && may_change_feed(&db, target_id, user_id, feed_url) {
+                    db.set_limits(feed_url, limits);
+                }
+                t!(
+                    lang,
+                    "sub.success",
+                    link = Escape(&feed.link),
+                    title = Escape(&feed.title)
+                )
+            } else {
+                t!(lang, "sub.already_subscribed").into()
+            }
+        }
+        Err(e) => t!(lang, "sub.failed", error = Escape(&e.to_string())),
+    }
+}
+
+/// Show a feed as it would be pushed, without subscribing to it
+pub async fn preview(
+    db: Arc<Mutex<Database>>,
+    cmd: Arc<Command<Text<Proxy>>>,
+) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
+    let target = &mut MsgTarget::new(cmd.chat.id, cmd.message_id);
+    let args: Vec<&str> = cmd.text.value.split_whitespace().collect();
+    let feed_url = match &*args {
+        [url] => *url,
+        [..] => {
+            let msg = t!(lang, "preview.usage");
+            update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+            return Ok(());
+        }
+    };
+    let msg = t!(lang, "processing");
+    update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+    let feed = match pull_feed(feed_url, None, &LimitsOverride::default()).await {
+        Ok(feed) => feed,
+        Err(e) => {
+            let msg = t!(lang, "preview.failed", error = Escape(&e.to_string()));
+            update_response(&cmd.bot, target, parameters::Text::html(&msg)).await?;
+            return Ok(());
+        }
+    };
+    let ttl = match feed.ttl {
+        Some(minutes) => {
+            HumanDuration(lang, Duration::from_secs(u64::from(minutes) * 60)).to_string()
+        }
+        None => t!(lang, "preview.no_ttl").to_string(),
+    };
+    let msg = t!(
+        lang,
+        "preview.info",
+        link = Escape(&feed.link),
+        title = Escape(&feed.title),
+        format = feed.format.map_or("?", Format::as_str),
+        ttl = ttl,
+        count = feed.items.len()
+    );
+    update_response(&cmd.bot, target, parameters::Text::html(&msg)).await?;
+    if !feed.items.is_empty() {
+        let items: Vec<&feed::Item> = feed.items.iter().take(PREVIEW_ITEMS).collect();
+        // Items without a link link to the feed, as when they are pushed
+        let msgs = render_update(&feed.title, feed_url, &items, None);
+        reply_msgs(&cmd, msgs).await?;
+    }
+    Ok(())
+}
+
+pub async fn unsub(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Proxy>>>) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
+    let chat_id = cmd.chat.id;
+    let text = &cmd.text.value;
+    let args = text.split_whitespace().collect::<Vec<_>>();
+    let mut target_id = chat_id;
+    let target = &mut MsgTarget::new(chat_id, cmd.message_id);
+
+    let (channel, feed_urls) = match &*args {
+        ["all"] => (None, Vec::new()),
+        [channel, "all"] => (Some(*channel), Vec::new()),
+        _ => {
+            let (channel, feed_urls) = feed_args(&args, "", reply_links(&cmd));
+            if feed_urls.is_empty() {
+                let msg = t!(lang, "unsub.usage");
+                update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+                return Ok(());
+            }
+            (channel, feed_urls)
+        }
+    };
+    if let Some(channel) = channel {
+        let user_id = cmd.from.as_ref().unwrap().id;
+        let channel_id = check_channel_permission(&cmd.bot, lang, channel, target, user_id).await?;
+        if channel_id.is_none() {
+            return Ok(());
+        }
+        target_id = channel_id.unwrap();
+    }
+    if feed_urls.is_empty() {
+        return confirm_unsub_all(&db, &cmd, lang, target_id, target).await;
+    }
+
+    let outcomes: Vec<Option<String>> = feed_urls
+        .iter()
+        .map(|feed_url| {
+            let msg = if let Some(feed) = db.lock().unwrap().unsubscribe(target_id.0, feed_url) {
+                t!(
+                    lang,
+                    "unsub.success",
+                    link = Escape(&feed.link),
+                    title = Escape(&feed.title)
+                )
+            } else {
+                t!(lang, "not_subscribed").into()
+            };
+            Some(msg)
+        })
+        .collect();
+    let msg = match &*outcomes {
+        [Some(msg)] => msg.clone(),
+        _ => bulk_report(lang, &feed_urls, &outcomes),
+    };
+    update_response(&cmd.bot, target, parameters::Text::html(&msg)).await?;
+    Ok(())
+}
+
+/// Ask the user to confirm `/unsub all` with the buttons handled by `callback`
+async fn confirm_unsub_all(
+    db: &Mutex<Database>,
+    cmd: &Command<Text<Proxy>>,
+    lang: Lang,
+    target_id: tbot::types::chat::Id,
+    target: &mut MsgTarget,
+) -> anyhow::Result<()> {
+    let count = db.lock().unwrap().subscription_count(target_id.0);
+    if count == 0 {
+        let msg = t!(lang, "rss.empty");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(());
+    }
+    // Only the user asked can press the buttons, nobody can without a user
+    let user_id = cmd.from.as_ref().map_or(0, |user| user.id.0);
+    let confirm = format!("unsub_all:{}:{}", target_id.0, user_id);
+    let cancel = format!("cancel:{}:{}", target_id.0, user_id);
+    let buttons: &[&[Button]] = &[&[
+        Button::new(
+            t!(lang, "unsub.confirm"),
+            ButtonKind::CallbackData(&confirm),
+        ),
+        Button::new(t!(lang, "unsub.cancel"), ButtonKind::CallbackData(&cancel)),
+    ]];
+    let msg = t!(lang, "unsub.confirm_all", count = count);
+    let text = parameters::Text::plain(&msg);
+    if target.first_time {
+        cmd.bot
+            .send_message(target.chat_id, text)
+            .reply_to_message_id(target.message_id)
+            .reply_markup(Keyboard::new(buttons))
+            .call()
+            .await?;
+    } else {
+        cmd.bot
+            .edit_message_text(target.chat_id, target.message_id, text)
+            .reply_markup(Keyboard::new(buttons))
             .call()
             .await?;
-        prev_msg = msg.id;
     }
     Ok(())
 }
 
-pub async fn sub(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Https>>>) -> anyhow::Result<()> {
+/// Handle the buttons of the confirmation asked by `/unsub all`
+pub async fn callback(
+    db: Arc<Mutex<Database>>,
+    ctx: Arc<DataCallback<Proxy>>,
+) -> anyhow::Result<()> {
+    let message = match &ctx.origin {
+        Origin::Message(message) => message,
+        _ => return Ok(()),
+    };
+    let lang = i18n::chat_lang(&db.lock().unwrap(), message.chat.id.0);
+    let mut parts = ctx.data.split(':');
+    let action = parts.next().unwrap_or_default();
+    let target_id = parts.next().and_then(|s| s.parse::<i64>().ok());
+    let user_id = parts.next().and_then(|s| s.parse::<i64>().ok());
+    let target_id = match (target_id, user_id) {
+        (Some(target_id), Some(user_id)) if user_id == ctx.from.id.0 => target_id,
+        _ => {
+            ctx.alert(t!(lang, "unsub.not_requester")).call().await?;
+            return Ok(());
+        }
+    };
+    let msg = match action {
+        "unsub_all" => {
+            // The user may no longer manage the channel since asking
+            if target_id != message.chat.id.0
+                && !is_channel_admin(&ctx.bot, target_id, ctx.from.id).await?
+            {
+                ctx.alert(t!(lang, "channel.user_not_admin")).call().await?;
+                return Ok(());
+            }
+            let count = db.lock().unwrap().unsubscribe_all(target_id);
+            t!(lang, "unsub.all_done", count = count)
+        }
+        "cancel" => t!(lang, "unsub.cancelled").into(),
+        _ => return Ok(()),
+    };
+    ctx.ignore().call().await?;
+    ctx.bot
+        .edit_message_text(message.chat.id, message.id, parameters::Text::plain(&msg))
+        .call()
+        .await?;
+    Ok(())
+}
+
+/// Tag a subscription to group it, or clear its tag when no tag is given
+pub async fn tag(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Proxy>>>) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
     let chat_id = cmd.chat.id;
-    let text = &cmd.text.value;
+    let args: Vec<&str> = cmd.text.value.split_whitespace().collect();
+    let mut target_id = chat_id;
+    let target = &mut MsgTarget::new(chat_id, cmd.message_id);
+
+    let (channel, feed_url, tag) = match &*args {
+        [url] => (None, *url, None),
+        [channel, url] if is_channel(channel) => (Some(*channel), *url, None),
+        [url, tag] => (None, *url, Some(*tag)),
+        [channel, url, tag] => (Some(*channel), *url, Some(*tag)),
+        [..] => {
+            let msg = t!(lang, "tag.usage");
+            update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+            return Ok(());
+        }
+    };
+    if tag.map_or(false, |tag| tag.chars().count() > MAX_TAG_LEN) {
+        let msg = t!(lang, "tag.too_long", max = MAX_TAG_LEN);
+        update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+        return Ok(());
+    }
+    if let Some(channel) = channel {
+        let user_id = cmd.from.as_ref().unwrap().id;
+        let channel_id = check_channel_permission(&cmd.bot, lang, channel, target, user_id).await?;
+        if channel_id.is_none() {
+            return Ok(());
+        }
+        target_id = channel_id.unwrap();
+    }
+    let tagged = db
+        .lock()
+        .unwrap()
+        .set_tag(target_id.0, feed_url, tag.map(String::from));
+    let msg = if !tagged {
+        t!(lang, "not_subscribed").to_string()
+    } else if let Some(tag) = tag {
+        t!(lang, "tag.set", tag = tag)
+    } else {
+        t!(lang, "tag.cleared").to_string()
+    };
+    update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+    Ok(())
+}
+
+/// Move the subscriptions of a chat to another one
+pub async fn transfer(
+    db: Arc<Mutex<Database>>,
+    cmd: Arc<Command<Text<Proxy>>>,
+) -> anyhow::Result<()> {
+    copy_subscriptions(db, cmd, true).await
+}
+
+/// Subscribe a chat to the feeds of another one
+pub async fn copy(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Proxy>>>) -> anyhow::Result<()> {
+    copy_subscriptions(db, cmd, false).await
+}
+
+async fn copy_subscriptions(
+    db: Arc<Mutex<Database>>,
+    cmd: Arc<Command<Text<Proxy>>>,
+    transfer: bool,
+) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
+    let target = &mut MsgTarget::new(cmd.chat.id, cmd.message_id);
+    let args: Vec<&str> = cmd.text.value.split_whitespace().collect();
+    let (from, to) = match &*args {
+        [from, to] => (*from, *to),
+        [..] => {
+            let msg = if transfer {
+                t!(lang, "transfer.usage")
+            } else {
+                t!(lang, "copy.usage")
+            };
+            update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+            return Ok(());
+        }
+    };
+    let user_id = cmd.from.as_ref().unwrap().id;
+    let from_id = match check_chat_permission(&cmd.bot, lang, from, target, user_id).await? {
+        Some(id) => id.0,
+        None => return Ok(()),
+    };
+    let to_id = match check_chat_permission(&cmd.bot, lang, to, target, user_id).await? {
+        Some(id) => id.0,
+        None => return Ok(()),
+    };
+    if from_id == to_id {
+        let msg = t!(lang, "transfer.same_chat");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(());
+    }
+    let msg = {
+        let mut db = db.lock().unwrap();
+        if let Some(msg) = check_copy_quota(&db, lang, from_id, to_id, user_id.0, transfer) {
+            msg
+        } else if transfer {
+            let count = db.transfer_subscriptions(from_id, to_id, Some(user_id.0));
+            t!(lang, "transfer.done", count = count)
+        } else {
+            let count = db.copy_subscriptions(from_id, to_id, Some(user_id.0));
+            t!(lang, "copy.done", count = count)
+        }
+    };
+    update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+    Ok(())
+}
+
+pub async fn auth(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Proxy>>>) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
+    let chat_id = cmd.chat.id;
+    let (text, credentials_text) = split_first_line(&cmd.text.value);
     let args = text.split_whitespace().collect::<Vec<_>>();
     let mut target_id = chat_id;
     let target = &mut MsgTarget::new(chat_id, cmd.message_id);
     let feed_url;
 
+    if !config::get().features.credentials {
+        let msg = t!(lang, "auth.disabled");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(());
+    }
+    if !cmd.chat.kind.is_private() {
+        let msg = t!(lang, "auth.private_only");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(());
+    }
     match &*args {
         [url] => feed_url = url,
         [channel, url] => {
             let user_id = cmd.from.as_ref().unwrap().id;
-            let channel_id = check_channel_permission(&cmd.bot, channel, target, user_id).await?;
+            let channel_id =
+                check_channel_permission(&cmd.bot, lang, channel, target, user_id).await?;
             if channel_id.is_none() {
                 return Ok(());
             }
@@ -127,49 +734,71 @@ pub async fn sub(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Https>>>) -> an
             feed_url = url;
         }
         [..] => {
-            let msg = "使用方法: /sub [Channel ID] <RSS URL>";
-            update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+            let msg = t!(lang, "auth.usage");
+            update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
             return Ok(());
         }
     };
-    if db.lock().unwrap().is_subscribed(target_id.0, feed_url) {
-        update_response(&cmd.bot, target, parameters::Text::plain("已订阅过的 RSS")).await?;
+    if !db.lock().unwrap().is_subscribed(target_id.0, feed_url) {
+        let msg = t!(lang, "not_subscribed");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
         return Ok(());
     }
-
-    if cfg!(feature = "hosted-by-iovxw") && db.lock().unwrap().all_feeds().len() >= 1500 {
-        let msg = "已达到全局最大订阅数量, \
-                   为防止服务器压力过大请退订不需要的 RSS 或者\
-                   [自己搭建服务](https://github.com/iovxw/rssbot)\n\
-                   注: 本机器人主要用于提供即时提醒功能, 例如服务器状态监控和社区论坛提醒\n\
-                   默认更新频率为 5 分钟, 不建议用于其他类型的 RSS 订阅\n\
-                   如有相关需求推荐使用其他 RSS 机器人实现";
-        update_response(&cmd.bot, target, parameters::Text::markdown(msg)).await?;
+    if !db
+        .lock()
+        .unwrap()
+        .may_set_credentials(target_id.0, feed_url)
+    {
+        let msg = t!(lang, "auth.not_owner");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
         return Ok(());
     }
-    update_response(&cmd.bot, target, parameters::Text::plain("处理中，请稍候")).await?;
-    let msg = match pull_feed(feed_url).await {
-        Ok(feed) => {
-            if db.lock().unwrap().subscribe(target_id.0, feed_url, &feed) {
-                format!(
-                    "《<a href=\"{}\">{}</a>》 订阅成功",
-                    Escape(&feed.link),
-                    Escape(&feed.title)
-                )
+    let credentials = match Credentials::parse(credentials_text) {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            let msg = credentials_error(lang, &e);
+            update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+            return Ok(());
+        }
+    };
+    if credentials.is_empty() {
+        db.lock()
+            .unwrap()
+            .set_credentials(target_id.0, feed_url, None);
+        let msg = t!(lang, "auth.cleared");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(());
+    }
+
+    let msg = t!(lang, "processing");
+    update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+    let limits = db
+        .lock()
+        .unwrap()
+        .get_feed(feed_url)
+        .map(|feed| feed.limits)
+        .unwrap_or_default();
+    let msg = match pull_feed(feed_url, Some(&credentials), &limits).await {
+        Ok(_) => {
+            let sealed = credentials.seal(feed_url);
+            let mut db = db.lock().unwrap();
+            if db.set_credentials(target_id.0, feed_url, Some(sealed)) {
+                t!(lang, "auth.updated").into()
             } else {
-                "已订阅过的 RSS".into()
+                t!(lang, "not_subscribed").into()
             }
         }
-        Err(e) => format!("订阅失败：{}", Escape(&e.to_string())),
+        Err(e) => t!(lang, "auth.failed", error = Escape(&e.to_string())),
     };
     update_response(&cmd.bot, target, parameters::Text::html(&msg)).await?;
     Ok(())
 }
 
-pub async fn unsub(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Https>>>) -> anyhow::Result<()> {
+pub async fn set(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Proxy>>>) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
     let chat_id = cmd.chat.id;
     let text = &cmd.text.value;
-    let args = text.split_whitespace().collect::<Vec<_>>();
+    let (args, options) = split_options(text);
     let mut target_id = chat_id;
     let target = &mut MsgTarget::new(chat_id, cmd.message_id);
     let feed_url;
@@ -178,7 +807,8 @@ pub async fn unsub(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Https>>>) ->
         [url] => feed_url = url,
         [channel, url] => {
             let user_id = cmd.from.as_ref().unwrap().id;
-            let channel_id = check_channel_permission(&cmd.bot, channel, target, user_id).await?;
+            let channel_id =
+                check_channel_permission(&cmd.bot, lang, channel, target, user_id).await?;
             if channel_id.is_none() {
                 return Ok(());
             }
@@ -186,28 +816,131 @@ pub async fn unsub(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Https>>>) ->
             feed_url = url;
         }
         [..] => {
-            let msg = "使用方法: /unsub [Channel ID] <RSS URL>";
+            let msg = t!(
+                lang,
+                "set.usage",
+                max_size = ByteSize(MAX_FEED_SIZE),
+                timeout = MAX_TIMEOUT,
+                max_redirects = MAX_REDIRECTS
+            );
             update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
             return Ok(());
         }
     };
-    let msg = if let Some(feed) = db.lock().unwrap().unsubscribe(target_id.0, feed_url) {
-        format!(
-            "《<a href=\"{}\">{}</a>》 退订成功",
-            Escape(&feed.link),
-            Escape(&feed.title)
-        )
+    let feed = db
+        .lock()
+        .unwrap()
+        .get_feed(feed_url)
+        .filter(|feed| feed.subscribers.contains(&target_id.0));
+    let feed = if let Some(feed) = feed {
+        feed
     } else {
-        "未订阅过的 RSS".into()
+        let msg = t!(lang, "not_subscribed");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(());
     };
+    let (identity, options): (Vec<_>, Vec<_>) = options
+        .into_iter()
+        .partition(|option| option.starts_with("identity="));
+    let identity = match identity.last().map(|option| &option["identity=".len()..]) {
+        None => None,
+        Some("default") => Some(ItemIdentity::Auto),
+        Some(value) => match value.parse::<ItemIdentity>() {
+            Ok(identity) => Some(identity),
+            Err(()) => {
+                let available = ItemIdentity::ALL
+                    .iter()
+                    .map(|identity| identity.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let msg = t!(
+                    lang,
+                    "set.unknown_identity",
+                    value = value,
+                    available = available
+                );
+                update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+                return Ok(());
+            }
+        },
+    };
+    let (max_items, options): (Vec<_>, Vec<_>) = options
+        .into_iter()
+        .partition(|option| option.starts_with("max_items="));
+    let max_items = match max_items.last().map(|option| &option["max_items=".len()..]) {
+        None => None,
+        Some("default") => Some(None),
+        Some(value) => match value.parse::<usize>() {
+            Ok(n) if n > 0 => Some(Some(n)),
+            result => {
+                let error = match result {
+                    Ok(_) => t!(lang, "not_positive").to_string(),
+                    Err(e) => e.to_string(),
+                };
+                let msg = t!(lang, "set.invalid_value", key = "max_items", error = error);
+                update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+                return Ok(());
+            }
+        },
+    };
+    let limits = match parse_limits(lang, feed.limits, &options) {
+        Ok(limits) => limits,
+        Err(msg) => {
+            update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+            return Ok(());
+        }
+    };
+    let user_id = cmd.from.as_ref().map(|user| user.id.0);
+    let changes_feed = !options.is_empty() || identity.is_some();
+    if changes_feed && !may_change_feed(&db.lock().unwrap(), target_id.0, user_id, feed_url) {
+        let msg = t!(lang, "set.shared");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(());
+    }
+    if let Some(max_items) = max_items {
+        db.lock()
+            .unwrap()
+            .set_max_items(target_id.0, feed_url, max_items);
+    }
+    let max_items = max_items.unwrap_or_else(|| {
+        db.lock()
+            .unwrap()
+            .chat(target_id.0)
+            .and_then(|chat| chat.subscriptions.get(*feed_url))
+            .and_then(|subscription| subscription.max_items)
+    });
+    if !options.is_empty() {
+        db.lock().unwrap().set_limits(feed_url, limits);
+    }
+    if let Some(identity) = identity {
+        db.lock().unwrap().set_identity(feed_url, identity);
+    }
+    let identity = identity.unwrap_or(feed.identity);
+    let show = |value: Option<String>| value.unwrap_or_else(|| t!(lang, "set.default").into());
+    let msg = t!(
+        lang,
+        "set.limits",
+        link = Escape(&feed.link),
+        title = Escape(&feed.title),
+        max_size = show(limits.max_size.map(|size| ByteSize(size).to_string())),
+        timeout = show(
+            limits
+                .timeout
+                .map(|secs| t!(lang, "set.seconds", secs = secs))
+        ),
+        max_redirects = show(limits.max_redirects.map(|n| n.to_string())),
+        max_items = show(max_items.map(|n| n.to_string())),
+        identity = identity.as_str(),
+    );
     update_response(&cmd.bot, target, parameters::Text::html(&msg)).await?;
     Ok(())
 }
 
 pub async fn export(
     db: Arc<Mutex<Database>>,
-    cmd: Arc<Command<Text<Https>>>,
+    cmd: Arc<Command<Text<Proxy>>>,
 ) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
     let chat_id = cmd.chat.id;
     let channel = &cmd.text.value;
     let mut target_id = chat_id;
@@ -215,19 +948,23 @@ pub async fn export(
 
     if !channel.is_empty() {
         let user_id = cmd.from.as_ref().unwrap().id;
-        let channel_id = check_channel_permission(&cmd.bot, channel, target, user_id).await?;
+        let channel_id = check_channel_permission(&cmd.bot, lang, channel, target, user_id).await?;
         if channel_id.is_none() {
             return Ok(());
         }
         target_id = channel_id.unwrap();
     }
 
-    let feeds = db.lock().unwrap().subscribed_feeds(target_id.0);
+    let (feeds, tags) = {
+        let db = db.lock().unwrap();
+        (db.subscribed_feeds(target_id.0), db.tags(target_id.0))
+    };
     if feeds.is_none() {
-        update_response(&cmd.bot, target, parameters::Text::plain("订阅列表为空")).await?;
+        let msg = t!(lang, "rss.empty");
+        update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
         return Ok(());
     }
-    let opml = opml::into_opml(feeds.unwrap());
+    let opml = opml::into_opml(feeds.unwrap(), &tags);
 
     cmd.bot
         .send_document(
@@ -240,8 +977,609 @@ pub async fn export(
     Ok(())
 }
 
+/// Subscribe to the feeds in the replied-to OPML file, the folders become tags
+pub async fn import(
+    db: Arc<Mutex<Database>>,
+    cmd: Arc<Command<Text<Proxy>>>,
+) -> anyhow::Result<()> {
+    use tbot::types::message::Kind;
+    let lang = lang_of(&db, &cmd);
+    let chat_id = cmd.chat.id;
+    let channel = cmd.text.value.trim();
+    let mut target_id = chat_id;
+    let target = &mut MsgTarget::new(chat_id, cmd.message_id);
+
+    let document = match cmd.reply_to.as_ref().map(|message| &message.kind) {
+        Some(Kind::Document(document, _)) => document,
+        _ => {
+            let msg = t!(lang, "import.usage");
+            update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+            return Ok(());
+        }
+    };
+    if document
+        .file_size
+        .map_or(false, |size| size > MAX_OPML_SIZE)
+    {
+        let msg = t!(
+            lang,
+            "import.too_large",
+            max = ByteSize(u64::from(MAX_OPML_SIZE))
+        );
+        update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+        return Ok(());
+    }
+    if !channel.is_empty() {
+        let user_id = cmd.from.as_ref().unwrap().id;
+        let channel_id = check_channel_permission(&cmd.bot, lang, channel, target, user_id).await?;
+        if channel_id.is_none() {
+            return Ok(());
+        }
+        target_id = channel_id.unwrap();
+    }
+
+    let msg = t!(lang, "processing");
+    update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+    let file = cmd.bot.get_file(&**document).call().await?;
+    let data = cmd.bot.download_file(&file).await?;
+    let outlines = match opml::from_opml(&data) {
+        Ok(outlines) if outlines.is_empty() => {
+            let msg = t!(lang, "import.empty");
+            update_response(&cmd.bot, target, parameters::Text::plain(msg)).await?;
+            return Ok(());
+        }
+        Ok(outlines) => outlines,
+        Err(e) => {
+            let msg = t!(lang, "import.invalid", error = e);
+            update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+            return Ok(());
+        }
+    };
+    let mut feed_urls: Vec<String> = Vec::new();
+    for outline in &outlines {
+        if !feed_urls.contains(&outline.url) {
+            feed_urls.push(outline.url.clone());
+        }
+    }
+    if feed_urls.len() > MAX_BULK_URLS {
+        let msg = t!(lang, "bulk.too_many", max = MAX_BULK_URLS);
+        update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+        return Ok(());
+    }
+
+    let outcomes = subscribe_bulk(&db, &cmd, lang, target, target_id.0, &feed_urls, &[]).await;
+    {
+        let mut db = db.lock().unwrap();
+        for outline in outlines {
+            if let Some(tag) = outline.tag {
+                let tag = tag.chars().take(MAX_TAG_LEN).collect();
+                db.set_tag(target_id.0, &outline.url, Some(tag));
+            }
+        }
+    }
+    remember_lang(&db, &cmd, target_id.0);
+    let msg = bulk_report(lang, &feed_urls, &outcomes);
+    update_response(&cmd.bot, target, parameters::Text::html(&msg)).await?;
+    Ok(())
+}
+
+/// Show or set the language of the chat, `default` follows the users
+pub async fn lang(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Proxy>>>) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
+    let chat_id = cmd.chat.id;
+    let args = cmd.text.value.split_whitespace().collect::<Vec<_>>();
+    let mut target_id = chat_id;
+    let target = &mut MsgTarget::new(chat_id, cmd.message_id);
+    let value;
+
+    match &*args {
+        [] => value = None,
+        [code] => value = Some(code),
+        [channel, code] => {
+            let user_id = cmd.from.as_ref().unwrap().id;
+            let channel_id =
+                check_channel_permission(&cmd.bot, lang, channel, target, user_id).await?;
+            if channel_id.is_none() {
+                return Ok(());
+            }
+            target_id = channel_id.unwrap();
+            value = Some(code);
+        }
+        [..] => value = None,
+    }
+    let msg = match value {
+        None => {
+            let current = db
+                .lock()
+                .unwrap()
+                .chat(target_id.0)
+                .and_then(|chat| chat.lang);
+            let current = current
+                .map(|lang| t!(lang, "lang.name").to_string())
+                .unwrap_or_else(|| t!(lang, "lang.default").into());
+            let available = Lang::ALL
+                .iter()
+                .map(|&lang| format!("{} - {}", lang.code(), t!(lang, "lang.name")))
+                .collect::<Vec<_>>()
+                .join("\n");
+            t!(lang, "lang.usage", current = current, available = available)
+        }
+        Some(&"default") => {
+            db.lock().unwrap().set_lang(target_id.0, None);
+            t!(lang, "lang.reset").into()
+        }
+        Some(code) => match code.parse::<Lang>() {
+            Ok(new_lang) => {
+                db.lock().unwrap().set_lang(target_id.0, Some(new_lang));
+                t!(new_lang, "lang.set", name = t!(new_lang, "lang.name"))
+            }
+            Err(_) => t!(lang, "lang.unknown", code = code),
+        },
+    };
+    update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+    Ok(())
+}
+
+/// Suppress the items already delivered to the chat from other feeds
+pub async fn dedup(db: Arc<Mutex<Database>>, cmd: Arc<Command<Text<Proxy>>>) -> anyhow::Result<()> {
+    let lang = lang_of(&db, &cmd);
+    let chat_id = cmd.chat.id;
+    let args = cmd.text.value.split_whitespace().collect::<Vec<_>>();
+    let mut target_id = chat_id;
+    let target = &mut MsgTarget::new(chat_id, cmd.message_id);
+    let value;
+
+    match &*args {
+        [] => value = None,
+        [days] => value = Some(days),
+        [channel, days] => {
+            let user_id = cmd.from.as_ref().unwrap().id;
+            let channel_id =
+                check_channel_permission(&cmd.bot, lang, channel, target, user_id).await?;
+            if channel_id.is_none() {
+                return Ok(());
+            }
+            target_id = channel_id.unwrap();
+            value = Some(days);
+        }
+        [..] => value = None,
+    }
+    let msg = match value {
+        None => {
+            let days = db
+                .lock()
+                .unwrap()
+                .chat(target_id.0)
+                .and_then(|chat| chat.dedup_days);
+            let current = match days {
+                Some(days) => t!(lang, "dedup.days", days = days),
+                None => t!(lang, "dedup.off").into(),
+            };
+            t!(lang, "dedup.usage", current = current)
+        }
+        Some(&"off") => {
+            db.lock().unwrap().set_dedup(target_id.0, None);
+            t!(lang, "dedup.disabled").into()
+        }
+        Some(days) => match days.parse::<u32>() {
+            Ok(days) if days > 0 => {
+                db.lock().unwrap().set_dedup(target_id.0, Some(days));
+                t!(lang, "dedup.enabled", days = days)
+            }
+            Ok(_) => t!(lang, "dedup.invalid", error = t!(lang, "not_positive")),
+            Err(e) => t!(lang, "dedup.invalid", error = e),
+        },
+    };
+    update_response(&cmd.bot, target, parameters::Text::plain(&msg)).await?;
+    Ok(())
+}
+
+/// The limits and the item identity are shared by the subscribers of a feed,
+/// only the administrators and the only subscriber can change them
+fn may_change_feed(db: &Database, chat: i64, user: Option<i64>, feed_url: &str) -> bool {
+    user.map_or(false, |user| config::get().is_admin(user))
+        || !db.has_other_subscribers(chat, feed_url)
+}
+
+/// Why the chat can't subscribe the feed, if it can't
+///
+/// The bot administrators have no limits, and a chat with a quota set by
+/// them isn't limited by the per-user limit.
+fn check_quota(
+    db: &Database,
+    lang: Lang,
+    chat: i64,
+    user: Option<i64>,
+    feed_url: &str,
+) -> Option<String> {
+    let config = config::get();
+    if user.map_or(false, |user| config.is_admin(user)) {
+        return None;
+    }
+    let limits = &config.limits;
+    if let Some(max) = limits.max_feeds {
+        if !db.has_feed(feed_url) && db.feed_count() >= max {
+            return Some(t!(lang, "quota.max_feeds", max = max));
+        }
+    }
+    let quota = db.chat(chat).and_then(|chat| chat.quota);
+    if let Some(max) = quota.or(limits.max_subscriptions_per_chat) {
+        if db.subscription_count(chat) >= max {
+            return Some(t!(lang, "quota.max_subscriptions_per_chat", max = max));
+        }
+    }
+    if let (None, Some(max), Some(user)) = (quota, limits.max_feeds_per_user, user) {
+        if db.subscriptions_added_by(user) >= max {
+            return Some(t!(lang, "quota.max_feeds_per_user", max = max));
+        }
+    }
+    None
+}
+
+/// The quota `to` would exceed with the subscriptions of `from`, the copies
+/// count as added by `user`, and the originals are gone after a transfer
+fn check_copy_quota(
+    db: &Database,
+    lang: Lang,
+    from: i64,
+    to: i64,
+    user: i64,
+    transfer: bool,
+) -> Option<String> {
+    let config = config::get();
+    if config.is_admin(user) {
+        return None;
+    }
+    let feeds = db.subscribed_feeds(from).unwrap_or_default();
+    let new = feeds
+        .iter()
+        .filter(|feed| !db.is_subscribed(to, &feed.link))
+        .count();
+    let quota = db.chat(to).and_then(|chat| chat.quota);
+    if let Some(max) = quota.or(config.limits.max_subscriptions_per_chat) {
+        if db.subscription_count(to) + new > max {
+            return Some(t!(lang, "quota.max_subscriptions_per_chat", max = max));
+        }
+    }
+    if let (None, Some(max)) = (quota, config.limits.max_feeds_per_user) {
+        let freed = match db.chat(from) {
+            Some(chat) if transfer => chat
+                .subscriptions
+                .values()
+                .filter(|subscription| subscription.added_by == Some(user))
+                .count(),
+            _ => 0,
+        };
+        if db.subscriptions_added_by(user) + new > max + freed {
+            return Some(t!(lang, "quota.max_feeds_per_user", max = max));
+        }
+    }
+    None
+}
+
+fn credentials_error(lang: Lang, e: &CredentialsError) -> String {
+    let reason = match e {
+        CredentialsError::Syntax(line) => t!(lang, "auth.syntax_error", line = line),
+        CredentialsError::InvalidHeader(line) => t!(lang, "auth.invalid_header", line = line),
+        CredentialsError::Decrypt => e.to_string(),
+    };
+    t!(lang, "auth.invalid", reason = reason)
+}
+
+/// Apply `key=value` options to the per-feed limits
+fn parse_limits(
+    lang: Lang,
+    mut limits: LimitsOverride,
+    options: &[&str],
+) -> Result<LimitsOverride, String> {
+    fn parse_number<T>(s: &str) -> Result<T, String>
+    where
+        T: std::str::FromStr,
+        T::Err: std::fmt::Display,
+    {
+        s.parse().map_err(|e: T::Err| e.to_string())
+    }
+
+    /// `default` resets the option
+    fn parse_option<T, F>(lang: Lang, value: &str, parse: F, max: T) -> Result<Option<T>, String>
+    where
+        T: PartialOrd + std::fmt::Display,
+        F: Fn(&str) -> Result<T, String>,
+    {
+        if value == "default" {
+            return Ok(None);
+        }
+        let value = parse(value)?;
+        if value > max {
+            Err(t!(lang, "set.too_large", max = max))
+        } else {
+            Ok(Some(value))
+        }
+    }
+    for option in options {
+        let mut kv = option.splitn(2, '=');
+        let (key, value) = (kv.next().unwrap(), kv.next().unwrap_or_default());
+        let result = match key {
+            "max_size" => parse_option(lang, value, parse_size, MAX_FEED_SIZE)
+                .map(|size| limits.max_size = size),
+            "timeout" => parse_option(lang, value, parse_number, MAX_TIMEOUT)
+                .map(|secs| limits.timeout = secs.map(|secs| secs.max(1))),
+            "max_redirects" => parse_option(lang, value, parse_number, MAX_REDIRECTS)
+                .map(|n| limits.max_redirects = n),
+            _ => return Err(t!(lang, "set.unknown_limit", key = key)),
+        };
+        result.map_err(|e| t!(lang, "set.invalid_value", key = key, error = e))?;
+    }
+    Ok(limits)
+}
+
+/// Split the arguments taken by position, the channel and the URL, from the
+/// `key=value` options after them. The keys are plain words, so a URL with
+/// `=` in its query is never taken as an option
+fn split_options(text: &str) -> (Vec<&str>, Vec<&str>) {
+    let args: Vec<&str> = text.split_whitespace().collect();
+    let start = args
+        .iter()
+        .position(|arg| is_option(arg))
+        .unwrap_or(args.len());
+    let (args, options) = args.split_at(start);
+    (args.to_vec(), options.to_vec())
+}
+
+fn is_option(arg: &str) -> bool {
+    match arg.find('=') {
+        Some(i) => i > 0 && arg[..i].chars().all(|c| c.is_ascii_lowercase() || c == '_'),
+        None => false,
+    }
+}
+
+fn sort_by_title(feeds: &mut [Feed]) {
+    feeds.sort_by_cached_key(|feed| {
+        feed.title
+            .chars()
+            .map(|c| {
+                c.to_pinyin()
+                    .map(Pinyin::plain)
+                    .map(Either::Right)
+                    .unwrap_or_else(|| Either::Left(c))
+            })
+            .collect::<Vec<Either<char, &str>>>()
+    });
+}
+
+/// Commands from others are ignored silently
+fn is_admin(cmd: &Command<Text<Proxy>>) -> bool {
+    cmd.from
+        .as_ref()
+        .map_or(false, |user| config::get().is_admin(user.id.0))
+}
+
+/// Language of the replies, set by `/lang` or of the user's Telegram client
+fn lang_of(db: &Mutex<Database>, cmd: &Command<Text<Proxy>>) -> Lang {
+    let (chat_lang, detected_lang) = db
+        .lock()
+        .unwrap()
+        .chat(cmd.chat.id.0)
+        .map_or((None, None), |chat| (chat.lang, chat.detected_lang));
+    chat_lang
+        .or_else(|| user_lang(cmd))
+        .or(detected_lang)
+        .unwrap_or(config::get().lang)
+}
+
+/// The language of the user who sent the command, by their Telegram client
+fn user_lang(cmd: &Command<Text<Proxy>>) -> Option<Lang> {
+    cmd.from
+        .as_ref()
+        .and_then(|user| user.language_code.as_ref())
+        .and_then(|code| Lang::from_code(code))
+}
+
+/// Send messages as a chain of replies to the command
+async fn reply_msgs(
+    cmd: &Command<Text<Proxy>>,
+    msgs: Vec<String>,
+) -> Result<(), tbot::errors::MethodCall> {
+    let mut prev_msg = cmd.message_id;
+    for msg in msgs {
+        let text = parameters::Text::html(&msg);
+        let msg = cmd
+            .bot
+            .send_message(cmd.chat.id, text)
+            .reply_to_message_id(prev_msg)
+            .web_page_preview(WebPagePreviewState::Disabled)
+            .call()
+            .await?;
+        prev_msg = msg.id;
+    }
+    Ok(())
+}
+
+/// Whether the argument is a URL rather than a channel or an option
+fn is_url(s: &str) -> bool {
+    s.contains("://")
+}
+
+/// Channels are given by `@username` or by ID, anything else is a feed URL,
+/// which may lack the scheme
+fn is_channel(s: &str) -> bool {
+    s.starts_with('@') || s.parse::<i64>().is_ok()
+}
+
+/// The channel and the feed URLs given to a command, the URLs may also
+/// come from the following lines, or else from the links in the replied-to message
+fn feed_args<'a>(
+    args: &[&'a str],
+    lines: &str,
+    reply_links: Vec<String>,
+) -> (Option<&'a str>, Vec<String>) {
+    let (channel, args) = match args {
+        [first, rest @ ..] if is_channel(first) => (Some(*first), rest),
+        _ => (None, args),
+    };
+    let given: Vec<&str> = args
+        .iter()
+        .copied()
+        .chain(lines.split_whitespace())
+        .collect();
+    let found = if given.is_empty() {
+        reply_links
+    } else {
+        given.into_iter().map(String::from).collect()
+    };
+    let mut urls: Vec<String> = Vec::new();
+    for url in found {
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    (channel, urls)
+}
+
+/// Split the lines following a command into the URL lines and the credentials
+/// lines, a line is credentials only if it parses as them and doesn't start
+/// with a URL, which would parse as a header named after the scheme
+fn split_credentials(lines: &str) -> (Vec<&str>, Vec<&str>) {
+    lines.lines().partition(|line| {
+        line.split_whitespace().next().map_or(true, is_url) || Credentials::parse(line).is_err()
+    })
+}
+
+/// The links in the message the command replies to
+fn reply_links(cmd: &Command<Text<Proxy>>) -> Vec<String> {
+    cmd.reply_to.as_ref().map(message_links).unwrap_or_default()
+}
+
+/// The links in the text or the caption of a message
+fn message_links(message: &tbot::types::Message) -> Vec<String> {
+    use tbot::types::message::{text::EntityKind, Kind};
+    let text = match &message.kind {
+        Kind::Text(text)
+        | Kind::Audio(_, text)
+        | Kind::Document(_, text)
+        | Kind::Photo(_, text, _)
+        | Kind::Video(_, text, _)
+        | Kind::Voice(_, text)
+        | Kind::Animation(_, text) => text,
+        _ => return Vec::new(),
+    };
+    // The entities are located in UTF-16 code units
+    let units: Vec<u16> = text.value.encode_utf16().collect();
+    text.entities
+        .iter()
+        .filter_map(|entity| match &entity.kind {
+            EntityKind::Url => units
+                .get(entity.offset..entity.offset + entity.length)
+                .map(String::from_utf16_lossy),
+            EntityKind::TextLink(url) => Some(url.clone()),
+            _ => None,
+        })
+        .map(|link| {
+            if is_url(&link) {
+                link
+            } else {
+                format!("http://{}", link)
+            }
+        })
+        .collect()
+}
+
+/// The outcomes of a command on several feeds, one line per feed done
+fn bulk_report(lang: Lang, feed_urls: &[String], outcomes: &[Option<String>]) -> String {
+    let done = outcomes.iter().filter(|outcome| outcome.is_some()).count();
+    let mut msg = t!(lang, "bulk.progress", done = done, total = feed_urls.len());
+    for (feed_url, outcome) in feed_urls.iter().zip(outcomes) {
+        if let Some(outcome) = outcome {
+            msg.push_str(&format!("\n{}: {}", Escape(feed_url), outcome));
+        }
+    }
+    truncate_html(&msg, TELEGRAM_MAX_MSG_LEN)
+}
+
+/// Like `check_channel_permission`, but also accepts the groups administrated
+/// by the user and the private chat with the user
+async fn check_chat_permission(
+    bot: &tbot::Bot<Proxy>,
+    lang: Lang,
+    chat: &str,
+    target: &mut MsgTarget,
+    user_id: tbot::types::user::Id,
+) -> Result<Option<tbot::types::chat::Id>, tbot::errors::MethodCall> {
+    use tbot::errors::MethodCall;
+    if chat.parse::<i64>().ok() == Some(user_id.0) {
+        return Ok(Some(tbot::types::chat::Id(user_id.0)));
+    }
+    let chat_id = chat
+        .parse::<i64>()
+        .map(|id| parameters::ChatId::Id(id.into()))
+        .unwrap_or_else(|_| parameters::ChatId::Username(chat));
+    let msg = t!(lang, "chat.verifying");
+    update_response(bot, target, parameters::Text::plain(msg)).await?;
+
+    let found = match bot.get_chat(chat_id).call().await {
+        Err(MethodCall::RequestError {
+            description,
+            error_code: 400,
+            ..
+        }) => {
+            let msg = t!(lang, "chat.not_found", error = description);
+            update_response(bot, target, parameters::Text::plain(&msg)).await?;
+            return Ok(None);
+        }
+        other => other?,
+    };
+    if found.kind.is_channel() {
+        if !config::get().features.channels {
+            let msg = t!(lang, "channel.disabled");
+            update_response(bot, target, parameters::Text::plain(msg)).await?;
+            return Ok(None);
+        }
+        return check_channel_admins(bot, lang, &found, target, user_id).await;
+    }
+    if !found.kind.is_group() && !found.kind.is_supergroup() {
+        let msg = t!(lang, "chat.unsupported");
+        update_response(bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(None);
+    }
+    let admins = match bot.get_chat_administrators(chat_id).call().await {
+        Err(MethodCall::RequestError {
+            description,
+            error_code: 400,
+            ..
+        }) => {
+            let msg = t!(lang, "chat.not_found", error = description);
+            update_response(bot, target, parameters::Text::plain(&msg)).await?;
+            return Ok(None);
+        }
+        other => other?,
+    };
+    if !admins.iter().any(|member| member.user.id == user_id) {
+        let msg = t!(lang, "chat.user_not_admin");
+        update_response(bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(None);
+    }
+    Ok(Some(found.id))
+}
+
+/// Whether the user administrates the channel
+async fn is_channel_admin(
+    bot: &tbot::Bot<Proxy>,
+    channel: i64,
+    user_id: tbot::types::user::Id,
+) -> Result<bool, tbot::errors::MethodCall> {
+    let channel = parameters::ChatId::Id(channel.into());
+    let admins = bot.get_chat_administrators(channel).call().await?;
+    Ok(admins.iter().any(|member| member.user.id == user_id))
+}
+
+/// Split the command arguments from the following lines
+fn split_first_line(text: &str) -> (&str, &str) {
+    let mut parts = text.splitn(2, '\n');
+    (parts.next().unwrap(), parts.next().unwrap_or_default())
+}
+
 async fn update_response(
-    bot: &tbot::Bot<Https>,
+    bot: &tbot::Bot<Proxy>,
     target: &mut MsgTarget,
     message: parameters::Text<'_>,
 ) -> Result<(), tbot::errors::MethodCall> {
@@ -262,7 +1600,8 @@ async fn update_response(
 }
 
 async fn check_channel_permission(
-    bot: &tbot::Bot<Https>,
+    bot: &tbot::Bot<Proxy>,
+    lang: Lang,
     channel: &str,
     target: &mut MsgTarget,
     user_id: tbot::types::user::Id,
@@ -272,7 +1611,13 @@ async fn check_channel_permission(
         .parse::<i64>()
         .map(|id| parameters::ChatId::Id(id.into()))
         .unwrap_or_else(|_| parameters::ChatId::Username(channel));
-    update_response(bot, target, parameters::Text::plain("正在验证 Channel")).await?;
+    if !config::get().features.channels {
+        let msg = t!(lang, "channel.disabled");
+        update_response(bot, target, parameters::Text::plain(msg)).await?;
+        return Ok(None);
+    }
+    let msg = t!(lang, "channel.verifying");
+    update_response(bot, target, parameters::Text::plain(msg)).await?;
 
     let chat = match bot.get_chat(channel_id).call().await {
         Err(MethodCall::RequestError {
@@ -280,23 +1625,37 @@ async fn check_channel_permission(
             error_code: 400,
             ..
         }) => {
-            let msg = format!("无法找到目标 Channel：{}", description);
+            let msg = t!(lang, "channel.not_found", error = description);
             update_response(bot, target, parameters::Text::plain(&msg)).await?;
             return Ok(None);
         }
         other => other?,
     };
     if !chat.kind.is_channel() {
-        update_response(bot, target, parameters::Text::plain("目标需为 Channel")).await?;
+        let msg = t!(lang, "channel.not_channel");
+        update_response(bot, target, parameters::Text::plain(msg)).await?;
         return Ok(None);
     }
+    check_channel_admins(bot, lang, &chat, target, user_id).await
+}
+
+/// Both the user and the bot must administrate the channel
+async fn check_channel_admins(
+    bot: &tbot::Bot<Proxy>,
+    lang: Lang,
+    chat: &tbot::types::Chat,
+    target: &mut MsgTarget,
+    user_id: tbot::types::user::Id,
+) -> Result<Option<tbot::types::chat::Id>, tbot::errors::MethodCall> {
+    use tbot::errors::MethodCall;
+    let channel_id = parameters::ChatId::Id(chat.id);
     let admins = match bot.get_chat_administrators(channel_id).call().await {
         Err(MethodCall::RequestError {
             description,
             error_code: 400,
             ..
         }) => {
-            let msg = format!("无法获取频道信息（{}），请将本 Bot 设为管理员", description);
+            let msg = t!(lang, "channel.no_admins", error = description);
             update_response(bot, target, parameters::Text::plain(&msg)).await?;
             return Ok(None);
         }
@@ -307,12 +1666,8 @@ async fn check_channel_permission(
         .find(|member| member.user.id == user_id)
         .is_some();
     if !user_is_admin {
-        update_response(
-            bot,
-            target,
-            parameters::Text::plain("该命令只能由 Channel 管理员使用"),
-        )
-        .await?;
+        let msg = t!(lang, "channel.user_not_admin");
+        update_response(bot, target, parameters::Text::plain(msg)).await?;
         return Ok(None);
     }
     let bot_is_admin = admins
@@ -320,13 +1675,90 @@ async fn check_channel_permission(
         .find(|member| member.user.id == *crate::BOT_ID.get().unwrap())
         .is_some();
     if !bot_is_admin {
-        update_response(
-            bot,
-            target,
-            parameters::Text::plain("请将本 Bot 设为管理员"),
-        )
-        .await?;
+        let msg = t!(lang, "channel.bot_not_admin");
+        update_response(bot, target, parameters::Text::plain(msg)).await?;
         return Ok(None);
     }
     Ok(Some(chat.id))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn links(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(|url| url.to_string()).collect()
+    }
+
+    #[test]
+    fn feed_args_channel() {
+        let args = ["@channel", "https://a.com/feed"];
+        assert_eq!(
+            feed_args(&args, "", Vec::new()),
+            (Some("@channel"), links(&["https://a.com/feed"]))
+        );
+        let args = ["-1001234", "https://a.com/feed"];
+        assert_eq!(feed_args(&args, "", Vec::new()).0, Some("-1001234"));
+        // Only the channel, the URLs are on the following lines
+        let args = ["@channel"];
+        assert_eq!(
+            feed_args(&args, "https://a.com/feed\nhttps://b.com/feed", Vec::new()),
+            (
+                Some("@channel"),
+                links(&["https://a.com/feed", "https://b.com/feed"])
+            )
+        );
+    }
+
+    #[test]
+    fn feed_args_without_scheme() {
+        let args = ["a.com/feed"];
+        let reply = links(&["https://b.com/feed"]);
+        assert_eq!(feed_args(&args, "", reply), (None, links(&["a.com/feed"])));
+        let args = ["a.com/feed", "b.com/feed", "a.com/feed"];
+        assert_eq!(
+            feed_args(&args, "", Vec::new()),
+            (None, links(&["a.com/feed", "b.com/feed"]))
+        );
+    }
+
+    #[test]
+    fn credentials_lines() {
+        let lines = "example.com/a\nhttps://b.com/feed c.com/feed\n\
+                     basic alice secret\nX-Token: 123\nbearer abc";
+        let (urls, credentials) = split_credentials(lines);
+        assert_eq!(urls, vec!["example.com/a", "https://b.com/feed c.com/feed"]);
+        assert_eq!(
+            credentials,
+            vec!["basic alice secret", "X-Token: 123", "bearer abc"]
+        );
+        let (channel, urls) = feed_args(&["@channel"], &urls.join("\n"), Vec::new());
+        assert_eq!(channel, Some("@channel"));
+        assert_eq!(
+            urls,
+            links(&["example.com/a", "https://b.com/feed", "c.com/feed"])
+        );
+    }
+
+    #[test]
+    fn feed_args_reply() {
+        let reply = || links(&["https://b.com/feed", "https://b.com/feed"]);
+        assert_eq!(
+            feed_args(&[], "", reply()),
+            (None, links(&["https://b.com/feed"]))
+        );
+        assert_eq!(
+            feed_args(&["@channel"], "", reply()),
+            (Some("@channel"), links(&["https://b.com/feed"]))
+        );
+        // The URLs given in the command win over the replied-to message
+        assert_eq!(
+            feed_args(&["https://a.com/feed"], "", reply()),
+            (None, links(&["https://a.com/feed"]))
+        );
+        assert_eq!(
+            feed_args(&[], "https://a.com/feed", reply()),
+            (None, links(&["https://a.com/feed"]))
+        );
+    }
+}
@@ -1,14 +1,16 @@
+use std::collections::{BTreeMap, HashMap};
 use std::io::Cursor;
 use std::io::Write;
 
 use chrono::Local;
 use quick_xml::events::attributes::Attribute;
 use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
-use quick_xml::Writer;
+use quick_xml::{Reader, Writer};
 
 use crate::data::Feed;
 
-pub fn into_opml(feeds: Vec<Feed>) -> String {
+/// The feeds tagged by `/tag` are put in a folder outline per tag
+pub fn into_opml(feeds: Vec<Feed>, tags: &HashMap<String, String>) -> String {
     let mut writer = Writer::new(Cursor::new(Vec::new()));
     let decl = BytesDecl::new(b"1.0", Some(b"UTF-8"), None);
     writer.write_event(Event::Decl(decl)).unwrap();
@@ -38,12 +40,24 @@ pub fn into_opml(feeds: Vec<Feed>) -> String {
                 })
             })?;
             with_tag(writer, b"body", &mut [], move |writer| {
-                for feed in feeds {
-                    let mut outline = BytesStart::borrowed(b"outline", 7);
-                    outline.push_attribute(Attribute::from(("type", "rss")));
-                    outline.push_attribute(Attribute::from(("text", feed.title.as_str())));
-                    outline.push_attribute(Attribute::from(("xmlUrl", feed.link.as_str())));
-                    writer.write_event(Event::Empty(outline))?;
+                let mut folders: BTreeMap<&str, Vec<&Feed>> = BTreeMap::new();
+                for feed in &feeds {
+                    match tags.get(&feed.link) {
+                        Some(tag) => folders.entry(tag).or_default().push(feed),
+                        None => write_outline(writer, feed, None)?,
+                    }
+                }
+                for (tag, feeds) in folders {
+                    let attrs = &mut [
+                        Attribute::from(("text", tag)).into(),
+                        Attribute::from(("title", tag)).into(),
+                    ];
+                    with_tag(writer, b"outline", attrs, |writer| {
+                        for feed in feeds {
+                            write_outline(writer, feed, Some(tag))?;
+                        }
+                        Ok(())
+                    })?;
                 }
                 Ok(())
             })
@@ -54,6 +68,90 @@ pub fn into_opml(feeds: Vec<Feed>) -> String {
     unsafe { String::from_utf8_unchecked(writer.into_inner().into_inner()) }
 }
 
+/// A feed listed in an OPML file
+#[derive(Debug, PartialEq)]
+pub struct Outline {
+    pub url: String,
+    /// The innermost folder of the feed, or else the last part of its category
+    pub tag: Option<String>,
+}
+
+/// The feeds in an OPML file, the outlines without `xmlUrl` are the folders
+pub fn from_opml(data: &[u8]) -> quick_xml::Result<Vec<Outline>> {
+    let mut reader = Reader::from_reader(data);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    // The outlines being read, with the names of the folders
+    let mut open: Vec<Option<String>> = Vec::new();
+    let mut outlines = Vec::new();
+    loop {
+        buf.clear();
+        let (e, empty) = match reader.read_event(&mut buf)? {
+            Event::Start(e) => (e, false),
+            Event::Empty(e) => (e, true),
+            Event::End(ref e) if e.name() == b"outline" => {
+                open.pop();
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+        if e.name() != b"outline" {
+            continue;
+        }
+        let (mut url, mut text, mut title, mut category) = (None, None, None, None);
+        for attr in e.attributes() {
+            let attr = attr?;
+            let value = Some(attr.unescape_and_decode_value(&reader)?);
+            match attr.key {
+                b"xmlUrl" => url = value,
+                b"text" => text = value,
+                b"title" => title = value,
+                b"category" => category = value,
+                _ => (),
+            }
+        }
+        let folder = match url {
+            Some(url) => {
+                let category = category.as_ref().and_then(|category| {
+                    // Comma-separated slash-delimited paths
+                    let path = category.split(',').next().unwrap();
+                    path.split('/').map(str::trim).rfind(|s| !s.is_empty())
+                });
+                let tag = open.iter().rev().flatten().next().map(String::as_str);
+                outlines.push(Outline {
+                    url,
+                    tag: tag.or(category).map(String::from),
+                });
+                None
+            }
+            None => text.or(title).filter(|name| !name.trim().is_empty()),
+        };
+        if !empty {
+            open.push(folder);
+        }
+    }
+    Ok(outlines)
+}
+
+fn write_outline<W: Write>(
+    writer: &mut Writer<W>,
+    feed: &Feed,
+    tag: Option<&str>,
+) -> quick_xml::Result<()> {
+    let mut outline = BytesStart::borrowed(b"outline", 7);
+    outline.push_attribute(Attribute::from(("type", "rss")));
+    outline.push_attribute(Attribute::from(("text", feed.title.as_str())));
+    outline.push_attribute(Attribute::from(("xmlUrl", feed.link.as_str())));
+    if let Some(tag) = tag {
+        // Categories are slash-delimited paths in OPML 2.0
+        let category = format!("/{}", tag);
+        outline.push_attribute(Attribute::from(("category", category.as_str())));
+    }
+    writer.write_event(Event::Empty(outline))?;
+    Ok(())
+}
+
 // type of `attrs` is for zero allocation
 fn with_tag<'a, W, F>(
     writer: &mut Writer<W>,
@@ -100,5 +198,95 @@ fn test_to_opml() {
          </opml>",
         Local::now().format("%a, %d %b %Y %T %Z")
     );
-    assert_eq!(into_opml(feeds), r);
+    assert_eq!(into_opml(feeds, &HashMap::new()), r);
+}
+
+#[test]
+fn test_to_opml_with_tags() {
+    let feeds: Vec<Feed> = (1..=3)
+        .map(|i| {
+            let mut feed = Feed::default();
+            feed.title = format!("title{}", i);
+            feed.link = format!("link{}", i);
+            feed
+        })
+        .collect();
+    let mut tags = HashMap::new();
+    tags.insert("link1".to_string(), "news".to_string());
+    tags.insert("link3".to_string(), "news".to_string());
+    let opml = into_opml(feeds, &tags);
+    let body = &opml[opml.find("<body>").unwrap()..];
+    assert_eq!(
+        body,
+        "<body>\
+         <outline type=\"rss\" text=\"title2\" xmlUrl=\"link2\"/>\
+         <outline text=\"news\" title=\"news\">\
+         <outline type=\"rss\" text=\"title1\" xmlUrl=\"link1\" category=\"/news\"/>\
+         <outline type=\"rss\" text=\"title3\" xmlUrl=\"link3\" category=\"/news\"/>\
+         </outline>\
+         </body>\
+         </opml>"
+    );
+}
+
+#[test]
+fn test_from_opml() {
+    let opml = br#"<?xml version="1.0" encoding="UTF-8"?>
+        <opml version="2.0">
+        <head><title>Feeds</title></head>
+        <body>
+          <outline type="rss" text="title1" xmlUrl="https://a.com/feed?a=1&amp;b=2"/>
+          <outline text="news" title="News">
+            <outline type="rss" text="title2" xmlUrl="https://b.com/feed"></outline>
+            <outline text="">
+              <outline type="rss" text="title3" xmlUrl="https://c.com/feed"/>
+            </outline>
+            <outline text="tech">
+              <outline type="rss" text="title4" xmlUrl="https://d.com/feed" category="/other"/>
+            </outline>
+          </outline>
+          <outline type="rss" text="title5" xmlUrl="https://e.com/feed" category="/a/b,/c"/>
+        </body>
+        </opml>"#;
+    let outline = |url: &str, tag: Option<&str>| Outline {
+        url: url.into(),
+        tag: tag.map(String::from),
+    };
+    assert_eq!(
+        from_opml(opml).unwrap(),
+        vec![
+            outline("https://a.com/feed?a=1&b=2", None),
+            outline("https://b.com/feed", Some("news")),
+            outline("https://c.com/feed", Some("news")),
+            outline("https://d.com/feed", Some("tech")),
+            outline("https://e.com/feed", Some("b")),
+        ]
+    );
+}
+
+#[test]
+fn test_opml_round_trip() {
+    let feeds: Vec<Feed> = (1..=2)
+        .map(|i| {
+            let mut feed = Feed::default();
+            feed.title = format!("title{}", i);
+            feed.link = format!("https://{}.com/feed", i);
+            feed
+        })
+        .collect();
+    let mut tags = HashMap::new();
+    tags.insert("https://2.com/feed".to_string(), "news".to_string());
+    let opml = into_opml(feeds, &tags);
+    let outlines = from_opml(opml.as_bytes()).unwrap();
+    assert_eq!(outlines[0].tag, None);
+    assert_eq!(outlines[1].tag.as_deref(), Some("news"));
+}
+
+#[test]
+fn test_to_opml_without_credentials() {
+    let mut feed = Feed::default();
+    feed.title = "title".into();
+    feed.link = "link".into();
+    feed.credentials = serde_json::from_str("\"c2VjcmV0\"").unwrap();
+    assert!(!into_opml(vec![feed], &HashMap::new()).contains("c2VjcmV0"));
 }
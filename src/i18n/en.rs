@@ -0,0 +1,282 @@
+pub const MESSAGES: &[(&str, &str)] = &[
+    (
+        "help",
+        "Commands:\n\
+         /rss       - List the subscribed feeds\n\
+         /sub       - Subscribe feeds: /sub http://example.com/feed.xml, several URLs at once are fine\n\
+         /preview   - Show the latest items of a feed without subscribing: /preview http://example.com/feed.xml\n\
+         /unsub     - Unsubscribe feeds: /unsub http://example.com/feed.xml, /unsub all for all of them\n\
+         /auth      - Set the credentials of a feed (private chat only), one HTTP header per line\n\
+         /set       - Set the pulling limits of a feed: /set http://example.com/feed.xml max_size=5M\n\
+         /status    - Show the pulling status of the subscribed feeds\n\
+         /export    - Export as OPML\n\
+         /import    - Subscribe the feeds in an OPML file, reply to the file with /import\n\
+         /lang      - Set the language: /lang zh\n\
+         /tag       - Tag a feed to group it in /rss and /export: /tag http://example.com/feed.xml news\n\
+         /transfer  - Move the subscriptions to another chat: /transfer -123 @channel\n\
+         /copy      - Copy the subscriptions to another chat: /copy -123 @channel\n\
+         /dedup     - Don't push the items got in the last days again: /dedup 3",
+    ),
+    ("processing", "Processing, please wait"),
+    ("not_subscribed", "Not subscribed"),
+    ("invalid_chat_id", "Invalid chat ID"),
+    ("not_positive", "must be greater than 0"),
+    ("yes", "yes"),
+    ("no", "no"),
+    ("unit.day", "d"),
+    ("unit.hour", "h"),
+    ("unit.minute", "min"),
+    ("unit.second", "s"),
+    ("rss.head", "Subscriptions:"),
+    ("rss.empty", "No subscriptions"),
+    ("status.head", "Status:"),
+    (
+        "status.last_success",
+        "  Last pulled: {ago} ago, {count} items",
+    ),
+    ("status.never_succeeded", "  Last pulled: unknown"),
+    ("status.last_error", "  Last error: {ago} ago, {error}"),
+    ("status.failures", "  Consecutive failures: {count}"),
+    ("status.next_pull", "  Next pull: in {left}"),
+    (
+        "sub.usage",
+        "Usage: /sub [Channel ID] <RSS URL>... [limit=value]...\n\
+         The URLs may also be on the following lines, or in the message replied to",
+    ),
+    ("sub.already_subscribed", "Already subscribed"),
+    ("sub.success", "Subscribed to <a href=\"{link}\">{title}</a>"),
+    ("sub.failed", "Failed to subscribe: {error}"),
+    ("preview.usage", "Usage: /preview <RSS URL>"),
+    ("preview.failed", "Failed to pull the feed: {error}"),
+    ("preview.no_ttl", "not given"),
+    (
+        "preview.info",
+        "<a href=\"{link}\">{title}</a>\n\
+         Format: {format}\n\
+         Update interval: {ttl}\n\
+         Items: {count}",
+    ),
+    (
+        "unsub.usage",
+        "Usage: /unsub [Channel ID] <RSS URL>...\n\
+         The URLs may also be in the message replied to, /unsub all unsubscribes from all the feeds",
+    ),
+    ("unsub.success", "Unsubscribed from <a href=\"{link}\">{title}</a>"),
+    (
+        "unsub.confirm_all",
+        "Unsubscribe from all the {count} feeds? Only the user who asked can answer",
+    ),
+    ("unsub.confirm", "Unsubscribe"),
+    ("unsub.cancel", "Cancel"),
+    ("unsub.not_requester", "Only the user who asked can answer"),
+    ("unsub.all_done", "Unsubscribed from {count} feeds"),
+    ("unsub.cancelled", "Cancelled"),
+    ("bulk.progress", "Done: {done}/{total}"),
+    ("bulk.too_many", "Too many URLs, at most {max} at once"),
+    ("bulk.credentials", "Credentials can only be given with a single URL"),
+    (
+        "auth.usage",
+        "Usage: /auth [Channel ID] <RSS URL>\n\
+         <HTTP headers>, one per line, e.g.:\n\
+         Authorization: Bearer xxx\n\
+         basic <username> <password>\n\
+         bearer <token>\n\
+         cookie <cookie>\n\
+         Without headers the credentials are cleared",
+    ),
+    ("auth.disabled", "Credentials are disabled on this bot"),
+    (
+        "auth.private_only",
+        "Credentials can only be set in a private chat with the bot",
+    ),
+    ("auth.invalid", "Invalid credentials: {reason}"),
+    (
+        "auth.syntax_error",
+        "line {line} should be `Header-Name: value`",
+    ),
+    ("auth.invalid_header", "invalid HTTP header at line {line}"),
+    ("auth.not_owner", "The credentials of this feed were set by another chat, only that chat can change them"),
+    ("auth.cleared", "Credentials cleared"),
+    ("auth.updated", "Credentials updated"),
+    ("auth.failed", "Authentication failed: {error}"),
+    (
+        "set.usage",
+        "Usage: /set [Channel ID] <RSS URL> [limit=value]...\n\
+         Limits:\n\
+         max_size      - Maximum size of the feed, e.g. 5M, at most {max_size}\n\
+         timeout       - Timeout in seconds, at most {timeout}\n\
+         max_redirects - Maximum number of redirects, at most {max_redirects}\n\
+         max_items     - Maximum items pushed to this chat for an update\n\
+         identity      - How to tell the items apart: auto, guid, link, normalized_link, title_link, content\n\
+         Use default as the value to reset it",
+    ),
+    (
+        "set.limits",
+        "Pulling limits of <a href=\"{link}\">{title}</a>:\n\
+         max_size: {max_size}\n\
+         timeout: {timeout}\n\
+         max_redirects: {max_redirects}\n\
+         max_items: {max_items}\n\
+         identity: {identity}",
+    ),
+    ("set.default", "default"),
+    (
+        "set.shared",
+        "Other chats subscribe to this feed too, only the bot administrators can change its limits and item identity",
+    ),
+    ("set.seconds", "{secs} s"),
+    ("set.too_large", "at most {max}"),
+    ("set.unknown_limit", "Unknown limit: {key}"),
+    (
+        "set.unknown_identity",
+        "Unknown identity: {value}, available: {available}",
+    ),
+    ("set.invalid_value", "Invalid value of {key}: {error}"),
+    ("lang.name", "English"),
+    ("lang.default", "default (the Telegram language of the users)"),
+    (
+        "lang.usage",
+        "Usage: /lang [Channel ID] <language|default>\n\
+         Current language: {current}\n\
+         Available languages:\n\
+         {available}",
+    ),
+    ("lang.set", "Language set to {name}"),
+    ("lang.reset", "Language reset to default"),
+    ("lang.unknown", "Unknown language: {code}"),
+    (
+        "dedup.usage",
+        "Usage: /dedup [Channel ID] <days|off>\n\
+         Don't push the items this chat got from other feeds in the last days\n\
+         Current: {current}",
+    ),
+    ("dedup.off", "off"),
+    ("dedup.days", "{days} days"),
+    ("dedup.enabled", "Items pushed in the last {days} days won't be pushed again"),
+    ("dedup.disabled", "Dedup disabled"),
+    ("dedup.invalid", "Invalid number of days: {error}"),
+    (
+        "quota.max_feeds",
+        "The bot reached its maximum number of feeds ({max}), please unsubscribe \
+         the feeds you don't need, or host your own: https://github.com/iovxw/rssbot",
+    ),
+    (
+        "quota.max_subscriptions_per_chat",
+        "This chat reached its maximum number of subscriptions ({max}), \
+         please unsubscribe the feeds you don't need first",
+    ),
+    (
+        "quota.max_feeds_per_user",
+        "You reached your maximum number of subscriptions ({max}), \
+         please unsubscribe the feeds you don't need first",
+    ),
+    (
+        "import.usage",
+        "Usage: reply /import [Channel ID] to an OPML file\n\
+         The feeds in folders are tagged by the folder",
+    ),
+    ("import.too_large", "The file is too large, at most {max}"),
+    ("import.invalid", "Invalid OPML: {error}"),
+    ("import.empty", "No feed found in the file"),
+    (
+        "tag.usage",
+        "Usage: /tag [Channel ID] <RSS URL> [tag]\n\
+         Groups the feed under the tag in /rss and /export, without a tag clears it",
+    ),
+    ("tag.too_long", "The tag is too long, {max} characters at most"),
+    ("tag.set", "Tagged as {tag}"),
+    ("tag.cleared", "Tag cleared"),
+    (
+        "transfer.usage",
+        "Usage: /transfer <from Chat ID> <to Chat ID>\n\
+         Moves the subscriptions with their settings, the chats may be channels, groups or your own chat",
+    ),
+    (
+        "copy.usage",
+        "Usage: /copy <from Chat ID> <to Chat ID>\n\
+         Copies the subscriptions with their settings, the chats may be channels, groups or your own chat",
+    ),
+    ("transfer.same_chat", "The chats must be different"),
+    ("transfer.done", "Moved, {count} feeds newly subscribed"),
+    ("copy.done", "Copied, {count} feeds newly subscribed"),
+    ("chat.verifying", "Verifying the chat"),
+    ("chat.not_found", "Chat not found: {error}"),
+    ("chat.unsupported", "The chat must be a channel, a group or your own chat"),
+    ("chat.user_not_admin", "Only the group administrators can use this command"),
+    ("channel.disabled", "Subscribing for channels is disabled on this bot"),
+    ("channel.verifying", "Verifying the channel"),
+    ("channel.not_found", "Channel not found: {error}"),
+    ("channel.not_channel", "The target must be a channel"),
+    (
+        "channel.no_admins",
+        "Failed to get the channel info ({error}), please make the bot an administrator",
+    ),
+    (
+        "channel.user_not_admin",
+        "Only the channel administrators can use this command",
+    ),
+    ("channel.bot_not_admin", "Please make the bot an administrator"),
+    (
+        "dead.warn",
+        "<a href=\"{link}\">{title}</a> has failed to pull for {duration} ({error}), \
+         it may be gone, please unsubscribe",
+    ),
+    (
+        "dead.warn_unsubscribe",
+        "<a href=\"{link}\">{title}</a> has failed to pull for {duration} ({error}), \
+         it may be gone, please unsubscribe, it will be unsubscribed automatically in {left}",
+    ),
+    (
+        "dead.unsubscribed",
+        "<a href=\"{link}\">{title}</a> has failed to pull for {duration} ({error}), \
+         unsubscribed automatically",
+    ),
+    ("more_items", "…and {count} more on <a href=\"{link}\">the site</a>"),
+    (
+        "renamed",
+        "<a href=\"{link}\">{title}</a> is renamed to {new_title}",
+    ),
+    (
+        "stats",
+        "Feeds: {feeds}\n\
+         Subscribers: {subscribers}\n\
+         Subscriptions: {subscriptions}\n\
+         Failing feeds: {failing}\n\
+         Queued messages: {queued}",
+    ),
+    ("broadcast.usage", "Usage: /broadcast <message>"),
+    ("broadcast.too_long", "The message is too long, at most {max} characters"),
+    ("broadcast.queued", "Queued for {count} subscribers"),
+    ("feedinfo.usage", "Usage: /feedinfo <RSS URL>"),
+    ("feedinfo.healthy", "healthy"),
+    (
+        "feedinfo.failing",
+        "{failures} consecutive failures, for {duration}",
+    ),
+    (
+        "feedinfo.head",
+        "<a href=\"{link}\">{title}</a>\n\
+         Status: {state}\n\
+         Next pull: in {next_pull}\n\
+         Credentials: {credentials}\n\
+         Subscribers ({count}):",
+    ),
+    ("feedinfo.added_by", "{chat} (added by {user})"),
+    (
+        "feedinfo.removed",
+        "Nobody subscribes this feed, it was unsubscribed automatically for \
+         {count} subscribers {ago} ago after failing for too long",
+    ),
+    ("feedinfo.unknown", "Nobody subscribes this feed"),
+    ("forceunsub.usage", "Usage: /forceunsub <Chat ID> <RSS URL>"),
+    (
+        "forceunsub.success",
+        "Unsubscribed {chat} from <a href=\"{link}\">{title}</a>",
+    ),
+    ("quota.usage", "Usage: /quota <Chat ID> [number|default]"),
+    ("quota.invalid", "Invalid number: {error}"),
+    ("quota.custom", "{max} (set for this chat)"),
+    ("quota.default", "{max} (default)"),
+    ("quota.unlimited", "unlimited"),
+    ("quota.show", "Subscriptions of {chat}: {count}, limit: {limit}"),
+];
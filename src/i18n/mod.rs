@@ -0,0 +1,235 @@
+//! Translations of the bot messages
+//!
+//! Messages are looked up by key with `t!`, `{name}` placeholders are
+//! filled with the named arguments:
+//!
+//! ```ignore
+//! t!(lang, "sub.success", link = Escape(&feed.link), title = Escape(&feed.title))
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::data::Database;
+
+mod en;
+mod zh;
+
+macro_rules! t {
+    ($lang: expr, $key: expr) => {
+        $crate::i18n::tr($lang, $key)
+    };
+    ($lang: expr, $key: expr, $($name: ident = $value: expr),+ $(,)?) => {
+        $crate::i18n::format(
+            $crate::i18n::tr($lang, $key),
+            &[$((stringify!($name), &$value as &dyn std::fmt::Display)),+],
+        )
+    };
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lang {
+    Zh,
+    En,
+}
+
+impl Lang {
+    pub const ALL: [Lang; 2] = [Lang::Zh, Lang::En];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::Zh => "zh",
+            Lang::En => "en",
+        }
+    }
+
+    /// From a language tag like the `language_code` of Telegram users, e.g. `zh-hans`
+    pub fn from_code(code: &str) -> Option<Lang> {
+        let primary = code
+            .split(|c| c == '-' || c == '_')
+            .next()
+            .unwrap_or_default();
+        Lang::ALL
+            .iter()
+            .copied()
+            .find(|lang| primary.eq_ignore_ascii_case(lang.code()))
+    }
+
+    fn messages(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Lang::Zh => zh::MESSAGES,
+            Lang::En => en::MESSAGES,
+        }
+    }
+}
+
+impl FromStr for Lang {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Lang, String> {
+        Lang::from_code(s).ok_or_else(|| format!("unknown language {}", s))
+    }
+}
+
+impl fmt::Display for Lang {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// The message of `key`, or the key itself if it's missing
+pub fn tr(lang: Lang, key: &'static str) -> &'static str {
+    match lang.messages().iter().find(|(k, _)| *k == key) {
+        Some((_, msg)) => msg,
+        None => {
+            warn!("missing translation of {} in {}", key, lang);
+            key
+        }
+    }
+}
+
+/// Fill the `{name}` placeholders of the template
+pub fn format(template: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let arg = rest.find('}').and_then(|end| {
+            let name = &rest[1..end];
+            args.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| (end, value))
+        });
+        match arg {
+            Some((end, value)) => {
+                result.push_str(&value.to_string());
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Language of the messages sent to the chat
+pub fn chat_lang(db: &Database, chat: i64) -> Lang {
+    db.chat(chat)
+        .and_then(|chat| chat.lang.or(chat.detected_lang))
+        .unwrap_or(config::get().lang)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+
+    fn placeholders(msg: &str) -> BTreeSet<&str> {
+        msg.split('{')
+            .skip(1)
+            .filter_map(|s| s.find('}').map(|end| &s[..end]))
+            .collect()
+    }
+
+    #[test]
+    fn every_key_in_every_locale() {
+        let keys = |lang: Lang| lang.messages().iter().map(|(k, _)| *k).collect::<Vec<_>>();
+        for &lang in &Lang::ALL {
+            let mut unique = keys(lang);
+            unique.sort();
+            unique.dedup();
+            assert_eq!(
+                unique.len(),
+                keys(lang).len(),
+                "duplicated keys in {}",
+                lang
+            );
+        }
+        for &(key, msg) in Lang::Zh.messages() {
+            for &lang in &Lang::ALL {
+                let translated = lang.messages().iter().find(|(k, _)| *k == key);
+                let translated =
+                    translated.unwrap_or_else(|| panic!("{} is missing in {}", key, lang));
+                assert_eq!(
+                    placeholders(msg),
+                    placeholders(translated.1),
+                    "placeholders of {} differ in {}",
+                    key,
+                    lang
+                );
+            }
+        }
+        for &lang in &Lang::ALL {
+            assert_eq!(
+                keys(lang).len(),
+                keys(Lang::Zh).len(),
+                "extra keys in {}",
+                lang
+            );
+        }
+    }
+
+    /// Every literal key passed to `t!` in the source has a translation
+    #[test]
+    fn used_keys_exist() {
+        fn visit(dir: &Path, keys: &mut Vec<String>) {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    visit(&path, keys);
+                } else if path.extension().map_or(false, |ext| ext == "rs") {
+                    let source = fs::read_to_string(&path).unwrap();
+                    for (i, _) in source.match_indices("t!(") {
+                        // Not `format!(` or the like
+                        let prev = source[..i].chars().last().unwrap_or(' ');
+                        if prev.is_alphanumeric() || prev == '_' {
+                            continue;
+                        }
+                        // The key is the second argument, skip it if it's not a literal
+                        let key = source[i + 3..].splitn(2, ',').nth(1).unwrap().trim_start();
+                        if key.starts_with('"') {
+                            keys.push(key[1..].split('"').next().unwrap().to_string());
+                        }
+                    }
+                }
+            }
+        }
+        let mut keys = Vec::new();
+        visit(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("src"),
+            &mut keys,
+        );
+        assert!(keys.len() > 50);
+        for key in keys {
+            assert!(
+                Lang::Zh.messages().iter().any(|(k, _)| *k == key),
+                "{} is not translated",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn fill_placeholders() {
+        assert_eq!(t!(Lang::En, "unit.day"), "d");
+        assert_eq!(
+            format("{a} and {b}, {c} {", &[("a", &1), ("b", &"x")]),
+            "1 and x, {c} {"
+        );
+        assert_eq!(Lang::from_code("zh-hans"), Some(Lang::Zh));
+        assert_eq!(Lang::from_code("EN_us"), Some(Lang::En));
+        assert_eq!(Lang::from_code("fr"), None);
+    }
+}
@@ -0,0 +1,282 @@
+pub const MESSAGES: &[(&str, &str)] = &[
+    (
+        "help",
+        "命令列表：\n\
+         /rss       - 显示当前订阅的 RSS 列表\n\
+         /sub       - 订阅 RSS: /sub http://example.com/feed.xml, 可以一次订阅多个\n\
+         /preview   - 预览一个 RSS 最新的条目, 不订阅: /preview http://example.com/feed.xml\n\
+         /unsub     - 退订 RSS: /unsub http://example.com/feed.xml, /unsub all 退订全部\n\
+         /auth      - 设置 RSS 的认证信息 (仅限私聊), 每行一个 HTTP 头\n\
+         /set       - 设置 RSS 的拉取限制: /set http://example.com/feed.xml max_size=5M\n\
+         /status    - 显示订阅的 RSS 的拉取状态\n\
+         /export    - 导出为 OPML\n\
+         /import    - 订阅 OPML 文件中的 RSS, 用 /import 回复该文件\n\
+         /lang      - 设置语言: /lang en\n\
+         /tag       - 给 RSS 设置标签, 在 /rss 和 /export 中分组: /tag http://example.com/feed.xml news\n\
+         /transfer  - 把订阅转移到另一个 Chat: /transfer -123 @channel\n\
+         /copy      - 把订阅复制到另一个 Chat: /copy -123 @channel\n\
+         /dedup     - 不重复推送最近几天内收到过的条目: /dedup 3",
+    ),
+    ("processing", "处理中，请稍候"),
+    ("not_subscribed", "未订阅过的 RSS"),
+    ("invalid_chat_id", "无效的 Chat ID"),
+    ("not_positive", "必须大于 0"),
+    ("yes", "有"),
+    ("no", "无"),
+    ("unit.day", "天"),
+    ("unit.hour", "小时"),
+    ("unit.minute", "分钟"),
+    ("unit.second", "秒"),
+    ("rss.head", "订阅列表："),
+    ("rss.empty", "订阅列表为空"),
+    ("status.head", "订阅状态："),
+    (
+        "status.last_success",
+        "  上次成功拉取: {ago} 前, 共 {count} 个条目",
+    ),
+    ("status.never_succeeded", "  上次成功拉取: 未知"),
+    ("status.last_error", "  上次错误: {ago} 前, {error}"),
+    ("status.failures", "  连续失败: {count} 次"),
+    ("status.next_pull", "  下次拉取: {left} 后"),
+    (
+        "sub.usage",
+        "使用方法: /sub [Channel ID] <RSS URL>... [限制=值]...\n\
+         RSS URL 也可以写在之后的行里, 或者在回复的消息中",
+    ),
+    ("sub.already_subscribed", "已订阅过的 RSS"),
+    ("sub.success", "《<a href=\"{link}\">{title}</a>》 订阅成功"),
+    ("sub.failed", "订阅失败：{error}"),
+    ("preview.usage", "使用方法: /preview <RSS URL>"),
+    ("preview.failed", "拉取失败：{error}"),
+    ("preview.no_ttl", "未指定"),
+    (
+        "preview.info",
+        "《<a href=\"{link}\">{title}</a>》\n\
+         格式：{format}\n\
+         更新间隔：{ttl}\n\
+         条目数：{count}",
+    ),
+    (
+        "unsub.usage",
+        "使用方法: /unsub [Channel ID] <RSS URL>...\n\
+         RSS URL 也可以在回复的消息中, /unsub all 退订全部 RSS",
+    ),
+    (
+        "unsub.success",
+        "《<a href=\"{link}\">{title}</a>》 退订成功",
+    ),
+    (
+        "unsub.confirm_all",
+        "确定退订全部 {count} 个 RSS 吗? 只有发出命令的用户可以回答",
+    ),
+    ("unsub.confirm", "退订"),
+    ("unsub.cancel", "取消"),
+    ("unsub.not_requester", "只有发出命令的用户可以回答"),
+    ("unsub.all_done", "已退订 {count} 个 RSS"),
+    ("unsub.cancelled", "已取消"),
+    ("bulk.progress", "已完成: {done}/{total}"),
+    ("bulk.too_many", "RSS URL 太多, 一次最多 {max} 个"),
+    ("bulk.credentials", "只有一个 RSS URL 时才能设置认证信息"),
+    (
+        "auth.usage",
+        "使用方法: /auth [Channel ID] <RSS URL>\n\
+         <HTTP 头>, 每行一个, 例如:\n\
+         Authorization: Bearer xxx\n\
+         basic <用户名> <密码>\n\
+         bearer <Token>\n\
+         cookie <Cookie>\n\
+         不带 HTTP 头则清除认证信息",
+    ),
+    ("auth.disabled", "本 Bot 未启用认证功能"),
+    ("auth.private_only", "认证信息只能私聊 Bot 设置"),
+    ("auth.invalid", "认证信息有误：{reason}"),
+    (
+        "auth.syntax_error",
+        "第 {line} 行格式错误, 应为 `Header-Name: value`",
+    ),
+    ("auth.invalid_header", "第 {line} 行的 HTTP 头无效"),
+    ("auth.not_owner", "该 RSS 的认证信息由其他聊天设置, 只有该聊天可以修改"),
+    ("auth.cleared", "认证信息已清除"),
+    ("auth.updated", "认证信息已更新"),
+    ("auth.failed", "认证失败：{error}"),
+    (
+        "set.usage",
+        "使用方法: /set [Channel ID] <RSS URL> [限制=值]...\n\
+         可用的限制:\n\
+         max_size      - RSS 大小上限, 例如 5M, 不超过 {max_size}\n\
+         timeout       - 超时秒数, 不超过 {timeout}\n\
+         max_redirects - 最大重定向次数, 不超过 {max_redirects}\n\
+         max_items     - 本聊天每次更新最多推送的条目数\n\
+         identity      - 判断条目是否相同的方式: auto, guid, link, normalized_link, title_link, content\n\
+         值为 default 时恢复默认设置",
+    ),
+    (
+        "set.limits",
+        "《<a href=\"{link}\">{title}</a>》 的拉取限制:\n\
+         max_size: {max_size}\n\
+         timeout: {timeout}\n\
+         max_redirects: {max_redirects}\n\
+         max_items: {max_items}\n\
+         identity: {identity}",
+    ),
+    ("set.default", "默认"),
+    (
+        "set.shared",
+        "该 RSS 还有其他聊天订阅, 只有 Bot 管理员可以修改它的拉取限制和条目判断方式",
+    ),
+    ("set.seconds", "{secs} 秒"),
+    ("set.too_large", "不能超过 {max}"),
+    ("set.unknown_limit", "未知的限制：{key}"),
+    (
+        "set.unknown_identity",
+        "未知的条目判断方式：{value}, 可用的方式: {available}",
+    ),
+    ("set.invalid_value", "{key} 的值无效：{error}"),
+    ("lang.name", "中文"),
+    ("lang.default", "默认 (跟随用户的 Telegram 语言)"),
+    (
+        "lang.usage",
+        "使用方法: /lang [Channel ID] <语言|default>\n\
+         当前语言: {current}\n\
+         可用的语言:\n\
+         {available}",
+    ),
+    ("lang.set", "语言已设置为{name}"),
+    ("lang.reset", "已恢复默认语言"),
+    ("lang.unknown", "未知的语言：{code}"),
+    (
+        "dedup.usage",
+        "使用方法: /dedup [Channel ID] <天数|off>\n\
+         不再推送本聊天在最近几天内已经从其他 RSS 收到过的条目\n\
+         当前设置: {current}",
+    ),
+    ("dedup.off", "关闭"),
+    ("dedup.days", "{days} 天"),
+    (
+        "dedup.enabled",
+        "最近 {days} 天内推送过的条目将不再重复推送",
+    ),
+    ("dedup.disabled", "已关闭去重"),
+    ("dedup.invalid", "无效的天数：{error}"),
+    (
+        "quota.max_feeds",
+        "已达到全局最大订阅数量 ({max}), 为防止服务器压力过大请退订不需要的 RSS 或者\
+         自己搭建服务: https://github.com/iovxw/rssbot",
+    ),
+    (
+        "quota.max_subscriptions_per_chat",
+        "已达到本聊天的最大订阅数量 ({max}), 请先退订不需要的 RSS",
+    ),
+    (
+        "quota.max_feeds_per_user",
+        "你添加的订阅已达到最大数量 ({max}), 请先退订不需要的 RSS",
+    ),
+    (
+        "import.usage",
+        "使用方法: 用 /import [Channel ID] 回复 OPML 文件\n\
+         文件夹中的 RSS 以文件夹名作为标签",
+    ),
+    ("import.too_large", "文件太大, 不能超过 {max}"),
+    ("import.invalid", "无效的 OPML：{error}"),
+    ("import.empty", "文件中没有 RSS"),
+    (
+        "tag.usage",
+        "使用方法: /tag [Channel ID] <RSS URL> [标签]\n\
+         在 /rss 和 /export 中把 RSS 归入标签下, 不写标签则清除",
+    ),
+    ("tag.too_long", "标签太长, 最多 {max} 个字符"),
+    ("tag.set", "已设置标签 {tag}"),
+    ("tag.cleared", "已清除标签"),
+    (
+        "transfer.usage",
+        "使用方法: /transfer <源 Chat ID> <目标 Chat ID>\n\
+         连同设置一起转移订阅, Chat 可以是 Channel, 群组或者你自己的私聊",
+    ),
+    (
+        "copy.usage",
+        "使用方法: /copy <源 Chat ID> <目标 Chat ID>\n\
+         连同设置一起复制订阅, Chat 可以是 Channel, 群组或者你自己的私聊",
+    ),
+    ("transfer.same_chat", "源和目标不能是同一个 Chat"),
+    ("transfer.done", "转移完成, 新订阅了 {count} 个 RSS"),
+    ("copy.done", "复制完成, 新订阅了 {count} 个 RSS"),
+    ("chat.verifying", "正在验证 Chat"),
+    ("chat.not_found", "无法找到目标 Chat：{error}"),
+    ("chat.unsupported", "Chat 只能是 Channel, 群组或者你自己的私聊"),
+    ("chat.user_not_admin", "该命令只能由群组管理员使用"),
+    ("channel.disabled", "本 Bot 未启用 Channel 订阅"),
+    ("channel.verifying", "正在验证 Channel"),
+    ("channel.not_found", "无法找到目标 Channel：{error}"),
+    ("channel.not_channel", "目标需为 Channel"),
+    (
+        "channel.no_admins",
+        "无法获取频道信息（{error}），请将本 Bot 设为管理员",
+    ),
+    ("channel.user_not_admin", "该命令只能由 Channel 管理员使用"),
+    ("channel.bot_not_admin", "请将本 Bot 设为管理员"),
+    (
+        "dead.warn",
+        "《<a href=\"{link}\">{title}</a>》已经连续 {duration} 拉取出错 ({error}), \
+         可能已经关闭, 请取消订阅",
+    ),
+    (
+        "dead.warn_unsubscribe",
+        "《<a href=\"{link}\">{title}</a>》已经连续 {duration} 拉取出错 ({error}), \
+         可能已经关闭, 请取消订阅, 将在 {left} 后自动退订",
+    ),
+    (
+        "dead.unsubscribed",
+        "《<a href=\"{link}\">{title}</a>》已经连续 {duration} 拉取出错 ({error}), \
+         已自动退订",
+    ),
+    ("more_items", "……还有 {count} 条, 见 <a href=\"{link}\">原网站</a>"),
+    (
+        "renamed",
+        "<a href=\"{link}\">{title}</a> 已更名为 {new_title}",
+    ),
+    (
+        "stats",
+        "RSS 数量: {feeds}\n\
+         订阅者数量: {subscribers}\n\
+         订阅数量: {subscriptions}\n\
+         拉取失败的 RSS: {failing}\n\
+         待发送的消息: {queued}",
+    ),
+    ("broadcast.usage", "使用方法: /broadcast <消息>"),
+    ("broadcast.too_long", "消息太长, 不能超过 {max} 个字符"),
+    ("broadcast.queued", "已加入发送队列, 共 {count} 个订阅者"),
+    ("feedinfo.usage", "使用方法: /feedinfo <RSS URL>"),
+    ("feedinfo.healthy", "正常"),
+    (
+        "feedinfo.failing",
+        "连续 {failures} 次拉取失败, 已持续 {duration}",
+    ),
+    (
+        "feedinfo.head",
+        "《<a href=\"{link}\">{title}</a>》\n\
+         状态: {state}\n\
+         下次拉取: {next_pull} 后\n\
+         认证信息: {credentials}\n\
+         订阅者 ({count}):",
+    ),
+    ("feedinfo.added_by", "{chat} (由 {user} 添加)"),
+    (
+        "feedinfo.removed",
+        "无人订阅的 RSS, 曾因连续拉取失败于 {ago} 前为 {count} 个订阅者自动退订",
+    ),
+    ("feedinfo.unknown", "无人订阅的 RSS"),
+    (
+        "forceunsub.usage",
+        "使用方法: /forceunsub <Chat ID> <RSS URL>",
+    ),
+    (
+        "forceunsub.success",
+        "已为 {chat} 退订《<a href=\"{link}\">{title}</a>》",
+    ),
+    ("quota.usage", "使用方法: /quota <Chat ID> [数量|default]"),
+    ("quota.invalid", "无效的数量：{error}"),
+    ("quota.custom", "{max} (单独设置)"),
+    ("quota.default", "{max} (默认)"),
+    ("quota.unlimited", "无限制"),
+    ("quota.show", "{chat} 的订阅数量: {count}, 上限: {limit}"),
+];
@@ -1,42 +1,114 @@
 #![feature(backtrace)]
 #![recursion_limit = "256"]
 
+use std::cmp;
+use std::env;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex}; // TODO: async Mutex
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Context;
+use futures::{
+    future::{self, FutureExt},
+    select_biased,
+};
+use log::{info, warn, LevelFilter};
 use once_cell::sync::OnceCell;
 use structopt::StructOpt;
 use tbot;
-use tokio;
+use tokio::{self, time};
+
+#[macro_use]
+mod i18n;
 
 mod client;
+mod config;
+mod credentials;
 mod data;
 mod feed;
 mod fetcher;
 mod gardener;
 mod handlers;
 mod messages;
+mod sender;
+mod shutdown;
 
+use crate::config::Config;
 use crate::data::Database;
 
+/// How long to wait for the pullings and sendings in progress on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 static BOT_NAME: OnceCell<String> = OnceCell::new();
 static BOT_ID: OnceCell<tbot::types::user::Id> = OnceCell::new();
 
 #[derive(Debug, StructOpt)]
 #[structopt(about = "A simple Telegram RSS bot.")]
 struct Opt {
-    /// Telegram bot token
-    token: String,
-    /// Path to database
-    #[structopt(short = "d", long, default_value = "./rssbot.json")]
-    database: PathBuf,
+    /// Telegram bot token, can also be set in $RSSBOT_TOKEN or the config file
+    token: Option<String>,
+    /// Path to config file, the arguments take precedence over it
+    #[structopt(short = "c", long)]
+    config: Option<PathBuf>,
+    /// Path to database [default: ./rssbot.json]
+    #[structopt(short = "d", long)]
+    database: Option<PathBuf>,
+
+    /// [default: 300]
+    #[structopt(long, parse(try_from_str = parse_interval))]
+    min_interval: Option<u32>,
+
+    /// [default: 43200]
+    #[structopt(long, parse(try_from_str = parse_interval))]
+    max_interval: Option<u32>,
+
+    /// Maximum size of a feed, e.g. 2M, 512K [default: 2M]
+    #[structopt(long, parse(try_from_str = client::parse_size))]
+    max_feed_size: Option<u64>,
+
+    /// Timeout of pulling a feed, in seconds [default: 10]
+    #[structopt(long, parse(try_from_str = parse_interval))]
+    timeout: Option<u32>,
+
+    /// Maximum number of redirects when pulling a feed [default: 5]
+    #[structopt(long)]
+    max_redirects: Option<usize>,
+
+    /// Maximum number of concurrent requests to the same host [default: 2]
+    #[structopt(long, parse(try_from_str = parse_interval))]
+    host_concurrency: Option<u32>,
+
+    /// Minimum spacing between requests to the same host, in milliseconds [default: 1000]
+    #[structopt(long)]
+    host_spacing: Option<u64>,
 
-    #[structopt(long, default_value = "300", parse(try_from_str = parse_interval))] // 5 minutes
-    min_interval: u32,
+    /// off, error, warn, info, debug or trace [default: info]
+    #[structopt(long)]
+    log_level: Option<String>,
+}
 
-    #[structopt(long, default_value = "43200", parse(try_from_str = parse_interval))] // 12 hours
-    max_interval: u32,
+impl Opt {
+    /// Override the config file, the token is looked up in the environment before it
+    fn merge_into(self, config: &mut Config) {
+        fn set<T>(value: Option<T>, to: &mut T) {
+            if let Some(value) = value {
+                *to = value;
+            }
+        }
+        let token = self.token.or_else(|| env::var(config::TOKEN_ENV).ok());
+        if token.is_some() {
+            config.token = token;
+        }
+        set(self.database, &mut config.database);
+        set(self.min_interval, &mut config.fetch.min_interval);
+        set(self.max_interval, &mut config.fetch.max_interval);
+        set(self.host_concurrency, &mut config.fetch.host_concurrency);
+        set(self.host_spacing, &mut config.fetch.host_spacing);
+        set(self.max_feed_size, &mut config.limits.max_feed_size);
+        set(self.timeout, &mut config.limits.timeout);
+        set(self.max_redirects, &mut config.limits.max_redirects);
+        set(self.log_level, &mut config.log.level);
+    }
 }
 
 fn parse_interval(s: &str) -> Result<u32, String> {
@@ -57,19 +129,34 @@ macro_rules! handle {
             let future = f(env.clone(), cmd);
             async {
                 if let Err(e) = future.await {
-                    dbg!(&e);
-                    dbg!(e.backtrace());
+                    log::error!("{:?}\n{:?}", e, e.backtrace());
                 }
             }
         }
     }};
 }
 
+/// Log our own messages at `level`, and only warnings from the dependencies
+fn init_logger(level: LevelFilter) {
+    env_logger::Builder::new()
+        .filter_level(cmp::min(level, LevelFilter::Warn))
+        .filter_module(env!("CARGO_PKG_NAME"), level)
+        .init();
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let opt = Opt::from_args();
-    let db = Arc::new(Mutex::new(Database::open(opt.database)?));
-    let bot = tbot::Bot::new(opt.token);
+    let mut config = Config::load(opt.config.as_deref())?;
+    opt.merge_into(&mut config);
+    config.validate()?;
+    init_logger(config.log_level().unwrap());
+    let token = config.token.clone().unwrap();
+
+    let db = Arc::new(Mutex::new(Database::open(config.database.clone())?));
+    credentials::init(&token);
+    client::init(config.client_limits(), config.feeds_proxy().unwrap());
+    let bot = tbot::Bot::with_connector(token, config.telegram_connector().unwrap());
     let me = bot
         .get_me()
         .call()
@@ -78,17 +165,60 @@ async fn main() -> anyhow::Result<()> {
 
     BOT_NAME.set(me.user.username.clone().unwrap()).unwrap();
     BOT_ID.set(me.user.id).unwrap();
+    config::init(config.clone());
 
+    data::start_saving(db.clone());
     gardener::start_pruning(bot.clone(), db.clone());
-    fetcher::start(bot.clone(), db.clone(), opt.min_interval, opt.max_interval);
+    let hosts = fetcher::HostThrottle::new(
+        config.fetch.host_concurrency as usize,
+        Duration::from_millis(config.fetch.host_spacing),
+    );
+    let (trigger, shutdown) = shutdown::channel();
+    let sender = sender::start(bot.clone(), db.clone(), shutdown.clone());
+    let fetcher = fetcher::start(
+        db.clone(),
+        config.fetch.min_interval,
+        config.fetch.max_interval,
+        hosts,
+        shutdown,
+    );
     let mut event_loop = bot.event_loop();
     event_loop.username(me.user.username.unwrap());
     event_loop.start(handle!(db, handlers::start));
     event_loop.command("rss", handle!(db, handlers::rss));
     event_loop.command("sub", handle!(db, handlers::sub));
+    event_loop.command("preview", handle!(db, handlers::preview));
     event_loop.command("unsub", handle!(db, handlers::unsub));
+    event_loop.command("auth", handle!(db, handlers::auth));
+    event_loop.command("set", handle!(db, handlers::set));
+    event_loop.command("status", handle!(db, handlers::status));
     event_loop.command("export", handle!(db, handlers::export));
+    event_loop.command("import", handle!(db, handlers::import));
+    event_loop.command("lang", handle!(db, handlers::lang));
+    event_loop.command("dedup", handle!(db, handlers::dedup));
+    event_loop.command("tag", handle!(db, handlers::tag));
+    event_loop.command("transfer", handle!(db, handlers::transfer));
+    event_loop.command("copy", handle!(db, handlers::copy));
+    event_loop.command("quota", handle!(db, handlers::quota));
+    event_loop.command("stats", handle!(db, handlers::stats));
+    event_loop.command("broadcast", handle!(db, handlers::broadcast));
+    event_loop.command("feedinfo", handle!(db, handlers::feedinfo));
+    event_loop.command("forceunsub", handle!(db, handlers::forceunsub));
+    event_loop.data_callback(handle!(db, handlers::callback));
+
+    select_biased! {
+        result = event_loop.polling().start().fuse() => {
+            result.unwrap();
+        }
+        _ = shutdown::signal().fuse() => (),
+    }
 
-    event_loop.polling().start().await.unwrap();
+    info!("shutting down");
+    trigger.fire();
+    let stopped = future::join(fetcher, sender);
+    if time::timeout(SHUTDOWN_TIMEOUT, stopped).await.is_err() {
+        warn!("timed out waiting for the messages being sent");
+    }
+    db.lock().unwrap().save()?;
     Ok(())
 }
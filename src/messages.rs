@@ -1,25 +1,513 @@
+use std::cmp;
 use std::fmt;
+use std::time::Duration;
+
+use crate::feed;
+use crate::i18n::Lang;
 
 pub const TELEGRAM_MAX_MSG_LEN: usize = 4096;
 
+/// Rendered length of the content shown for an item without a title
+const MAX_CONTENT_LEN: usize = 200;
+
+/// Join the lines into messages that fit in Telegram's limit, lines too long
+/// for a single message are truncated
 pub fn format_large_msg<T, F>(head: String, data: &[T], line_format_fn: F) -> Vec<String>
 where
     F: Fn(&T) -> String,
 {
+    let head = truncate_html(&head, TELEGRAM_MAX_MSG_LEN);
+    let mut len = rendered_len(&head);
     let mut msgs = vec![head];
     for item in data {
-        let line = line_format_fn(item);
-        if msgs.last_mut().unwrap().len() + line.len() > TELEGRAM_MAX_MSG_LEN {
+        let line = truncate_html(&line_format_fn(item), TELEGRAM_MAX_MSG_LEN);
+        let line_len = rendered_len(&line);
+        if len + 1 + line_len > TELEGRAM_MAX_MSG_LEN {
             msgs.push(line);
+            len = line_len;
         } else {
             let msg = msgs.last_mut().unwrap();
             msg.push('\n');
             msg.push_str(&line);
+            len += 1 + line_len;
         }
     }
     msgs
 }
 
+/// The messages of an update with the items, followed by the line about
+/// the ones left out
+pub fn render_update(
+    title: &str,
+    link: &str,
+    items: &[&feed::Item],
+    more: Option<String>,
+) -> Vec<String> {
+    let lines: Vec<String> = items
+        .iter()
+        .map(|item| {
+            let item_title = item.title.as_ref().map(|s| s.as_str()).unwrap_or(title);
+            let item_link = item.link.as_ref().map(|s| s.as_str()).unwrap_or(link);
+            let line = format!(
+                "<a href=\"{}\">{}</a>",
+                Escape(item_link),
+                Escape(item_title)
+            );
+            // Items without a title are told apart by the start of their content
+            match (&item.title, &item.content) {
+                (None, Some(content)) => {
+                    let content = truncate_html(&sanitize_html(content), MAX_CONTENT_LEN);
+                    format!("{}\n{}", line, content)
+                }
+                _ => line,
+            }
+        })
+        .chain(more)
+        .collect();
+    format_large_msg(format!("<b>{}</b>", Escape(title)), &lines, |line| {
+        line.clone()
+    })
+}
+
+/// Length of the text after Telegram parses the HTML, in UTF-16 code units,
+/// which is what the message length limit counts
+pub fn rendered_len(html: &str) -> usize {
+    HtmlTokens::telegram(html)
+        .map(|token| match token {
+            HtmlToken::Tag(_) => 0,
+            HtmlToken::Text(_, c) => c.len_utf16(),
+        })
+        .sum()
+}
+
+/// Cut the HTML to at most `max` rendered UTF-16 code units, ending with `…`.
+/// Tags and entities are never cut, and the tags left open are closed.
+pub fn truncate_html(html: &str, max: usize) -> String {
+    if rendered_len(html) <= max {
+        return html.to_string();
+    }
+    let budget = max.saturating_sub('…'.len_utf16());
+    let mut result = String::with_capacity(html.len().min(max * 4));
+    let mut open_tags = Vec::new();
+    let mut len = 0;
+    for token in HtmlTokens::telegram(html) {
+        match token {
+            HtmlToken::Tag(tag) => {
+                let name = tag_name(tag);
+                if tag.starts_with("<!") {
+                } else if tag.starts_with("</") {
+                    if let Some(i) = open_tags.iter().rposition(|open| *open == name) {
+                        open_tags.truncate(i);
+                    }
+                } else if !tag.ends_with("/>") {
+                    open_tags.push(name);
+                }
+                result.push_str(tag);
+            }
+            HtmlToken::Text(s, c) => {
+                if len + c.len_utf16() > budget {
+                    break;
+                }
+                len += c.len_utf16();
+                result.push_str(s);
+            }
+        }
+    }
+    result.push('…');
+    for name in open_tags.iter().rev() {
+        result.push_str("</");
+        result.push_str(name);
+        result.push('>');
+    }
+    result
+}
+
+fn tag_name(tag: &str) -> &str {
+    let tag = tag.trim_start_matches('<').trim_start_matches('/');
+    let end = tag
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .unwrap_or(tag.len());
+    &tag[..end]
+}
+
+enum HtmlToken<'a> {
+    /// `<a href="...">` or `</a>`
+    Tag(&'a str),
+    /// A character or an entity like `&amp;`, with the character it renders to
+    Text(&'a str, char),
+}
+
+struct HtmlTokens<'a> {
+    rest: &'a str,
+    entities: fn(&str) -> Option<char>,
+}
+
+impl<'a> HtmlTokens<'a> {
+    /// Tokens of the HTML subset Telegram accepts
+    fn telegram(html: &'a str) -> Self {
+        HtmlTokens {
+            rest: html,
+            entities: decode_entity,
+        }
+    }
+
+    /// Tokens of HTML from anywhere else, with more entities
+    fn html(html: &'a str) -> Self {
+        HtmlTokens {
+            rest: html,
+            entities: decode_html_entity,
+        }
+    }
+}
+
+impl<'a> Iterator for HtmlTokens<'a> {
+    type Item = HtmlToken<'a>;
+
+    fn next(&mut self) -> Option<HtmlToken<'a>> {
+        let s = self.rest;
+        let c = s.chars().next()?;
+        let is_tag = |s: &str| {
+            s[1..]
+                .chars()
+                .next()
+                .map_or(false, |c| c.is_ascii_alphabetic() || c == '/' || c == '!')
+        };
+        let (len, token) = match c {
+            '<' if s.starts_with("<!--") => {
+                let end = s.find("-->").map_or(s.len(), |end| end + 3);
+                (end, HtmlToken::Tag(&s[..end]))
+            }
+            '<' if is_tag(s) => match s.find('>') {
+                Some(end) => (end + 1, HtmlToken::Tag(&s[..=end])),
+                None => (s.len(), HtmlToken::Tag(s)),
+            },
+            '&' => match s
+                .find(';')
+                .filter(|&end| end <= 32)
+                .and_then(|end| (self.entities)(&s[1..end]).map(|c| (end, c)))
+            {
+                Some((end, decoded)) => (end + 1, HtmlToken::Text(&s[..=end], decoded)),
+                None => (1, HtmlToken::Text(&s[..1], c)),
+            },
+            _ => (c.len_utf8(), HtmlToken::Text(&s[..c.len_utf8()], c)),
+        };
+        self.rest = &s[len..];
+        Some(token)
+    }
+}
+
+/// The character of an entity without `&` and `;`, e.g. `amp` or `#x4e2d`
+fn decode_entity(entity: &str) -> Option<char> {
+    let code = match entity {
+        "lt" => return Some('<'),
+        "gt" => return Some('>'),
+        "amp" => return Some('&'),
+        "quot" => return Some('"'),
+        _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+            u32::from_str_radix(&entity[2..], 16).ok()?
+        }
+        _ if entity.starts_with('#') => entity[1..].parse().ok()?,
+        _ => return None,
+    };
+    std::char::from_u32(code)
+}
+
+fn decode_html_entity(entity: &str) -> Option<char> {
+    decode_entity(entity).or_else(|| {
+        let c = match entity {
+            "apos" => '\'',
+            "nbsp" | "ensp" | "emsp" | "thinsp" => ' ',
+            "shy" => '\u{ad}',
+            "copy" => '©',
+            "reg" => '®',
+            "trade" => '™',
+            "hellip" => '…',
+            "ndash" => '–',
+            "mdash" => '—',
+            "lsquo" => '‘',
+            "rsquo" => '’',
+            "ldquo" => '“',
+            "rdquo" => '”',
+            "laquo" => '«',
+            "raquo" => '»',
+            "middot" => '·',
+            "bull" => '•',
+            "times" => '×',
+            "deg" => '°',
+            _ => return None,
+        };
+        Some(c)
+    })
+}
+
+/// The text of the HTML, with the links listed after it as `[1] http://...`,
+/// for sending as plain text when Telegram rejects the HTML
+pub fn html_to_plain(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut links = Vec::new();
+    let mut in_link = false;
+    for token in HtmlTokens::html(html) {
+        match token {
+            HtmlToken::Tag(tag) if tag_name(tag).eq_ignore_ascii_case("a") => {
+                if tag.starts_with("</") {
+                    if in_link {
+                        text.push_str(&format!(" [{}]", links.len()));
+                    }
+                    in_link = false;
+                } else if let Some(href) = attribute(tag, "href") {
+                    links.push(href);
+                    in_link = true;
+                }
+            }
+            HtmlToken::Tag(_) => (),
+            HtmlToken::Text(_, c) => text.push(c),
+        }
+    }
+    if !links.is_empty() {
+        text.push('\n');
+        for (i, link) in links.iter().enumerate() {
+            text.push_str(&format!("\n[{}] {}", i + 1, link));
+        }
+    }
+    truncate_plain(text, TELEGRAM_MAX_MSG_LEN)
+}
+
+fn truncate_plain(mut text: String, max: usize) -> String {
+    let mut len = 0;
+    let budget = max.saturating_sub('…'.len_utf16());
+    let cut = text.char_indices().find(|&(_, c)| {
+        len += c.len_utf16();
+        len > budget
+    });
+    if let Some((i, _)) = cut {
+        if text.encode_utf16().count() > max {
+            text.truncate(i);
+            text.push('…');
+        }
+    }
+    text
+}
+
+/// Convert HTML from feeds into the subset Telegram accepts
+///
+/// Formatting tags are mapped to `b`, `i`, `u`, `s`, `a`, `code`, `pre` and
+/// `blockquote`, block elements become line breaks, everything else is
+/// dropped with its text kept, except scripts, styles and the like which
+/// are dropped entirely. The result is always properly nested.
+pub fn sanitize_html(html: &str) -> String {
+    let mut result = String::with_capacity(html.len());
+    // Tags opened in the result, and the ones ignored so their end tags are too
+    let mut open: Vec<(&str, bool)> = Vec::new();
+    let mut skipping: Option<String> = None;
+    let mut pending_space = false;
+    let mut pending_newlines = 0;
+    let mut has_text = false;
+    let flush = |result: &mut String, space: &mut bool, newlines: &mut usize, has_text: bool| {
+        if has_text {
+            if *newlines > 0 {
+                result.push_str(&"\n\n"[..cmp::min(*newlines, 2)]);
+            } else if *space {
+                result.push(' ');
+            }
+        }
+        *space = false;
+        *newlines = 0;
+    };
+    for token in HtmlTokens::html(html) {
+        let tag = match token {
+            HtmlToken::Text(_, c) => {
+                if skipping.is_some() {
+                    continue;
+                }
+                let in_pre = open.iter().any(|&(name, _)| name == "pre");
+                if c.is_whitespace() && !in_pre {
+                    pending_space = true;
+                    continue;
+                }
+                flush(
+                    &mut result,
+                    &mut pending_space,
+                    &mut pending_newlines,
+                    has_text,
+                );
+                let mut buf = [0; 4];
+                result.push_str(&Escape(c.encode_utf8(&mut buf)).to_string());
+                has_text = true;
+                continue;
+            }
+            HtmlToken::Tag(tag) => tag,
+        };
+        if tag.starts_with("<!") {
+            continue;
+        }
+        let closing = tag.starts_with("</");
+        let name = tag_name(tag).to_ascii_lowercase();
+        if let Some(skipped) = &skipping {
+            if closing && *skipped == name {
+                skipping = None;
+            }
+            continue;
+        }
+        let block_break = match name.as_str() {
+            "script" | "style" | "head" | "title" | "iframe" | "noscript" | "template"
+            | "object" | "svg" | "canvas" | "select" | "textarea" => {
+                if !closing && !tag.ends_with("/>") {
+                    skipping = Some(name);
+                }
+                continue;
+            }
+            "br" => Some(1),
+            "li" if !closing => {
+                pending_newlines = cmp::max(pending_newlines, 1);
+                flush(
+                    &mut result,
+                    &mut pending_space,
+                    &mut pending_newlines,
+                    has_text,
+                );
+                result.push_str("• ");
+                has_text = true;
+                continue;
+            }
+            "p" | "div" | "ul" | "ol" | "table" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6"
+            | "hr" | "section" | "article" | "figure" | "header" | "footer" | "dl" => Some(2),
+            "li" | "tr" | "dt" | "dd" | "figcaption" => Some(1),
+            _ => None,
+        };
+        if let Some(newlines) = block_break {
+            pending_newlines = cmp::max(pending_newlines, newlines);
+            continue;
+        }
+        let allowed = match name.as_str() {
+            "b" | "strong" => "b",
+            "i" | "em" | "cite" | "dfn" => "i",
+            "u" | "ins" => "u",
+            "s" | "strike" | "del" => "s",
+            "a" => "a",
+            "code" | "kbd" | "samp" | "tt" => "code",
+            "pre" => "pre",
+            "blockquote" => "blockquote",
+            _ => continue,
+        };
+        if closing {
+            if let Some(i) = open.iter().rposition(|&(name, _)| name == allowed) {
+                for &(name, emitted) in open[i..].iter().rev() {
+                    if emitted {
+                        result.push_str("</");
+                        result.push_str(name);
+                        result.push('>');
+                    }
+                }
+                open.truncate(i);
+            }
+            if allowed == "pre" || allowed == "blockquote" {
+                pending_newlines = cmp::max(pending_newlines, 1);
+            }
+            continue;
+        }
+        if tag.ends_with("/>") {
+            continue;
+        }
+        if allowed == "pre" || allowed == "blockquote" {
+            pending_newlines = cmp::max(pending_newlines, 1);
+        }
+        // Nothing can be nested in `code` and `pre` except `pre > code`, or a tag in itself
+        let innermost = open.iter().rev().find(|&&(_, emitted)| emitted);
+        let emit = if allowed == "code" && innermost == Some(&("pre", true)) {
+            true
+        } else {
+            !open.iter().any(|&(name, emitted)| {
+                emitted && (name == allowed || name == "code" || name == "pre")
+            })
+        };
+        let href = if allowed == "a" {
+            attribute(tag, "href")
+        } else {
+            None
+        };
+        let emit = emit
+            && (allowed != "a"
+                || href.as_ref().map_or(false, |href| {
+                    let href = href.to_ascii_lowercase();
+                    ["http://", "https://", "tg://", "mailto:"]
+                        .iter()
+                        .any(|scheme| href.starts_with(scheme))
+                }));
+        if emit {
+            flush(
+                &mut result,
+                &mut pending_space,
+                &mut pending_newlines,
+                has_text,
+            );
+            match href {
+                Some(href) if allowed == "a" => {
+                    result.push_str(&format!("<a href=\"{}\">", Escape(&href)))
+                }
+                _ => {
+                    result.push('<');
+                    result.push_str(allowed);
+                    result.push('>');
+                }
+            }
+        }
+        open.push((allowed, emit));
+    }
+    for &(name, emitted) in open.iter().rev() {
+        if emitted {
+            result.push_str("</");
+            result.push_str(name);
+            result.push('>');
+        }
+    }
+    result
+}
+
+/// Value of an attribute of the tag, with the entities decoded
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag[1..].trim_end_matches('>').trim_end_matches('/');
+    // Skip the tag name
+    rest = rest.trim_start_matches(|c: char| !c.is_whitespace());
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = rest[key_end..].trim_start();
+        let value = if rest.starts_with('=') {
+            rest = rest[1..].trim_start();
+            let (value, len) = match rest.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => {
+                    let end = rest[1..].find(quote).map_or(rest.len(), |end| end + 1);
+                    (&rest[1..end], cmp::min(end + 1, rest.len()))
+                }
+                _ => {
+                    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                    (&rest[..end], end)
+                }
+            };
+            rest = &rest[len..];
+            value
+        } else {
+            ""
+        };
+        if key.eq_ignore_ascii_case(name) {
+            return Some(
+                HtmlTokens::html(value)
+                    .map(|token| match token {
+                        HtmlToken::Tag(tag) => tag.to_string(),
+                        HtmlToken::Text(_, c) => c.to_string(),
+                    })
+                    .collect(),
+            );
+        }
+    }
+}
+
 pub struct Escape<'a>(pub &'a str);
 
 impl<'a> fmt::Display for Escape<'a> {
@@ -52,3 +540,313 @@ impl<'a> fmt::Display for Escape<'a> {
         Ok(())
     }
 }
+
+/// Human readable duration, keeps the two largest units, e.g. `3 天 2 小时`
+pub struct HumanDuration(pub Lang, pub Duration);
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let HumanDuration(lang, duration) = *self;
+        let units = [
+            (24 * 60 * 60, t!(lang, "unit.day")),
+            (60 * 60, t!(lang, "unit.hour")),
+            (60, t!(lang, "unit.minute")),
+            (1, t!(lang, "unit.second")),
+        ];
+        let mut secs = duration.as_secs();
+        let mut written = 0;
+        for &(unit, name) in &units {
+            if secs >= unit && written < 2 {
+                if written > 0 {
+                    fmt.write_str(" ")?;
+                }
+                write!(fmt, "{} {}", secs / unit, name)?;
+                secs %= unit;
+                written += 1;
+            } else if written > 0 {
+                break;
+            }
+        }
+        if written == 0 {
+            write!(fmt, "0 {}", t!(lang, "unit.second"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn render_content_without_title() {
+        let item = |title: Option<&str>| feed::Item {
+            title: title.map(Into::into),
+            link: Some("https://a.com/1".into()),
+            id: None,
+            content: Some("<p>Hello <script>x</script><b>world</b></p><img src=x>".into()),
+        };
+        let (titled, untitled) = (item(Some("Title")), item(None));
+        let msgs = render_update("Feed", "https://a.com/feed", &[&titled, &untitled], None);
+        assert_eq!(
+            msgs,
+            vec![
+                "<b>Feed</b>\n\
+                 <a href=\"https://a.com/1\">Title</a>\n\
+                 <a href=\"https://a.com/1\">Feed</a>\n\
+                 Hello <b>world</b>"
+            ]
+        );
+    }
+
+    #[test]
+    fn html_len() {
+        assert_eq!(rendered_len("abc"), 3);
+        assert_eq!(rendered_len("中文"), 2);
+        assert_eq!(rendered_len("😀"), 2);
+        assert_eq!(
+            rendered_len("<a href=\"http://example.com\">a&amp;b</a>"),
+            3
+        );
+        assert_eq!(rendered_len("&#x1F600;&#20013;&unknown;"), 2 + 1 + 9);
+        assert_eq!(rendered_len("1 < 2"), 5);
+    }
+
+    #[test]
+    fn truncate() {
+        assert_eq!(truncate_html("<b>abc</b>", 3), "<b>abc</b>");
+        assert_eq!(truncate_html("<b>abcd</b>", 3), "<b>ab…</b>");
+        assert_eq!(
+            truncate_html("<a href=\"x\">a&amp;b</a>c", 3),
+            "<a href=\"x\">a&amp;…</a>"
+        );
+        assert_eq!(truncate_html("😀😀", 3), "😀…");
+        assert_eq!(truncate_html("<b><i>a</i>bc</b>d", 2), "<b><i>a</i>…</b>");
+    }
+
+    #[test]
+    fn plain_fallback() {
+        assert_eq!(
+            html_to_plain("<b>Feed</b>\n<a href=\"http://a/?x=1&amp;y=2\">A &lt;1&gt;</a>\n<a href=\"http://b\">B</a> <a>C</a>"),
+            "Feed\nA <1> [1]\nB [2] C\n\n[1] http://a/?x=1&y=2\n[2] http://b"
+        );
+        assert_eq!(html_to_plain("1 < 2 & 3"), "1 < 2 & 3");
+        let long = html_to_plain(&"中".repeat(5000));
+        assert_eq!(long.chars().count(), TELEGRAM_MAX_MSG_LEN);
+        assert!(long.ends_with('…'));
+    }
+
+    #[test]
+    fn sanitize() {
+        assert_eq!(
+            sanitize_html("<p>Hello <strong>world</strong>!</p>\n<p>Second&nbsp;one</p>"),
+            "Hello <b>world</b>!\n\nSecond one"
+        );
+        assert_eq!(
+            sanitize_html("<div><img src=\"x.png\"><script>alert('<b>')</script>a<br/>b</div>"),
+            "a\nb"
+        );
+        assert_eq!(
+            sanitize_html("<ul><li>one</li><li>two &amp; <em>three</em></li></ul>"),
+            "• one\n• two &amp; <i>three</i>"
+        );
+        assert_eq!(
+            sanitize_html("<b><i>unclosed</b> <u>tags"),
+            "<b><i>unclosed</i></b> <u>tags</u>"
+        );
+        assert_eq!(
+            sanitize_html("<A HREF='http://example.com/?a=1&amp;b=\"2\"' target=_blank>link</A>"),
+            "<a href=\"http://example.com/?a=1&amp;b=&quot;2&quot;\">link</a>"
+        );
+        assert_eq!(
+            sanitize_html("<a href=\"javascript:alert(1)\">x</a> <a>y</a>"),
+            "x y"
+        );
+        assert_eq!(
+            sanitize_html(
+                "<pre><code class=\"rust\">fn main() {\n    <b>1 < 2</b>\n}</code></pre>"
+            ),
+            "<pre><code>fn main() {\n    1 &lt; 2\n}</code></pre>"
+        );
+        assert_eq!(
+            sanitize_html("<b>a <b>b</b> c</b><!-- <i> -->&copy; 1 < 2 &unknown;"),
+            "<b>a b c</b>© 1 &lt; 2 &amp;unknown;"
+        );
+        assert_eq!(
+            sanitize_html("x<blockquote>quoted</blockquote>y"),
+            "x\n<blockquote>quoted</blockquote>\ny"
+        );
+    }
+
+    #[test]
+    fn sanitize_random_html() {
+        let tags = [
+            "<p>",
+            "</p>",
+            "<b>",
+            "</b>",
+            "<strong>",
+            "</i>",
+            "<em>",
+            "<a href=\"http://a\">",
+            "</a>",
+            "<pre>",
+            "</pre>",
+            "<code>",
+            "</code>",
+            "<div>",
+            "<br>",
+            "<li>",
+            "<script>",
+            "</script>",
+            "<img src=x>",
+            "<",
+            ">",
+            "&",
+            "&amp;",
+            "&nbsp;",
+            "\"",
+            "中",
+            "😀",
+            " ",
+            "\n",
+            "a",
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let html: String = (0..rng.gen_range(0, 50))
+                .map(|_| tags[rng.gen_range(0, tags.len())])
+                .collect();
+            let sanitized = sanitize_html(&html);
+            assert!(balanced(&sanitized), "{} => {}", html, sanitized);
+            let mut open = Vec::new();
+            for token in HtmlTokens::telegram(&sanitized) {
+                match token {
+                    HtmlToken::Tag(tag) if tag.starts_with("</") => {
+                        open.pop();
+                    }
+                    HtmlToken::Tag(tag) => {
+                        let name = tag_name(tag);
+                        assert!(
+                            ["b", "i", "u", "s", "a", "code", "pre", "blockquote"].contains(&name),
+                            "{} => {}",
+                            html,
+                            sanitized
+                        );
+                        assert!(
+                            open.last() == Some(&"pre") && name == "code"
+                                || !open.iter().any(|&open| open == "code" || open == "pre"),
+                            "{} => {}",
+                            html,
+                            sanitized
+                        );
+                        open.push(name);
+                    }
+                    // `<`, `>` and `&` are always escaped
+                    HtmlToken::Text(s, c) => assert!(s.len() > 1 || !"<>&".contains(c)),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn split_by_rendered_len() {
+        // Each line is 2047 UTF-16 units but 6141 bytes, two lines and the
+        // newline between them are 4095 units and fit, the head doesn't as well
+        let lines = vec!["中".repeat(2047); 4];
+        let msgs = format_large_msg("<b>head</b>".into(), &lines, |line| line.clone());
+        assert_eq!(msgs.len(), 3);
+        assert_eq!(msgs[0], format!("<b>head</b>\n{}", lines[0]));
+        assert_eq!(msgs[1], format!("{}\n{}", lines[1], lines[2]));
+    }
+
+    fn random_line(rng: &mut StdRng) -> String {
+        let mut line = String::new();
+        let mut open = Vec::new();
+        for _ in 0..rng.gen_range(0, 5000) {
+            match rng.gen_range(0, 10) {
+                0 if open.is_empty() => {
+                    let tag = ["b", "i", "a", "code"][rng.gen_range(0, 4)];
+                    if tag == "a" {
+                        line.push_str("<a href=\"http://example.com/?a=1&amp;b=2\">");
+                    } else {
+                        line.push_str(&format!("<{}>", tag));
+                    }
+                    open.push(tag);
+                }
+                1 => {
+                    if let Some(tag) = open.pop() {
+                        line.push_str(&format!("</{}>", tag));
+                    }
+                }
+                2 => line.push_str(["&lt;", "&amp;", "&#x1F600;", "&quot;"][rng.gen_range(0, 4)]),
+                3 => line.push('😀'),
+                4 | 5 => line.push('中'),
+                _ => line.push('a'),
+            }
+        }
+        while let Some(tag) = open.pop() {
+            line.push_str(&format!("</{}>", tag));
+        }
+        line
+    }
+
+    fn balanced(html: &str) -> bool {
+        let mut open = Vec::new();
+        for token in HtmlTokens::telegram(html) {
+            if let HtmlToken::Tag(tag) = token {
+                if tag.starts_with("</") {
+                    if open.pop() != Some(tag_name(tag)) {
+                        return false;
+                    }
+                } else {
+                    open.push(tag_name(tag));
+                }
+            }
+        }
+        open.is_empty()
+    }
+
+    #[test]
+    fn split_random_lines() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..50 {
+            let lines = (0..rng.gen_range(0, 20))
+                .map(|_| random_line(&mut rng))
+                .collect::<Vec<_>>();
+            let msgs = format_large_msg("head".into(), &lines, |line| line.clone());
+            let mut splitted = Vec::new();
+            for msg in &msgs {
+                assert!(rendered_len(msg) <= TELEGRAM_MAX_MSG_LEN);
+                assert!(balanced(msg), "{}", msg);
+                splitted.extend(msg.split('\n'));
+            }
+            // The lines are kept in order, only the too long ones are truncated
+            assert_eq!(splitted.len(), lines.len() + 1);
+            for (line, splitted) in lines.iter().zip(&splitted[1..]) {
+                if rendered_len(line) <= TELEGRAM_MAX_MSG_LEN {
+                    assert_eq!(line, splitted);
+                } else {
+                    assert!(rendered_len(splitted) > TELEGRAM_MAX_MSG_LEN - 2);
+                    let text = splitted.trim_end_matches(|c| c != '…');
+                    assert!(line.starts_with(&text[..text.len() - '…'.len_utf8()]));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn human_duration() {
+        let d = |secs| HumanDuration(Lang::Zh, Duration::from_secs(secs)).to_string();
+        assert_eq!(d(0), "0 秒");
+        assert_eq!(d(59), "59 秒");
+        assert_eq!(d(5 * 60 + 3), "5 分钟 3 秒");
+        assert_eq!(d(3 * 24 * 60 * 60 + 2 * 60 * 60 + 7), "3 天 2 小时");
+        assert_eq!(d(24 * 60 * 60 + 5 * 60), "1 天");
+        let d = |secs| HumanDuration(Lang::En, Duration::from_secs(secs)).to_string();
+        assert_eq!(d(5 * 60 + 3), "5 min 3 s");
+    }
+}
@@ -0,0 +1,281 @@
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use futures::{future::FutureExt, select_biased};
+use log::warn;
+use tbot::{
+    connectors::Proxy,
+    errors::MethodCall,
+    types::parameters::{self, WebPagePreviewState},
+};
+use tokio::{
+    self,
+    task::JoinHandle,
+    time::{self, Duration, Instant},
+};
+
+use crate::data::{Database, Outgoing};
+use crate::shutdown::Shutdown;
+
+/// Telegram allows about 30 messages per second in total
+const GLOBAL_SPACING: Duration = Duration::from_millis(35);
+/// and 20 messages per minute in the same group or channel
+const GROUP_SPACING: Duration = Duration::from_secs(3);
+/// and about 1 message per second in the same private chat
+const PRIVATE_SPACING: Duration = Duration::from_secs(1);
+
+/// Give up a message after this many failed attempts
+const MAX_ATTEMPTS: u32 = 8;
+const RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Deliver the messages in outbox, in order for each chat
+///
+/// The returned handle resolves after the message being sent is done on
+/// shutdown, the rest are kept in the outbox for the next start.
+pub fn start(
+    bot: tbot::Bot<Proxy>,
+    db: Arc<Mutex<Database>>,
+    mut shutdown: Shutdown,
+) -> JoinHandle<()> {
+    let wakeup = db.lock().unwrap().outbox_wakeup();
+    let mut limiter = RateLimiter::new();
+    tokio::spawn(async move {
+        while !shutdown.is_triggered() {
+            let next = {
+                let db = db.lock().unwrap();
+                limiter.pick(db.outbox(), Instant::now(), SystemTime::now())
+            };
+            match next {
+                Pick::Send(msg) => {
+                    send(&bot, &db, &mut limiter, msg).await;
+                }
+                Pick::Wait(at) => {
+                    select_biased! {
+                        _ = shutdown.wait().fuse() => (),
+                        _ = wakeup.notified().fuse() => (),
+                        _ = time::delay_until(at).fuse() => (),
+                    }
+                }
+                Pick::Empty => {
+                    limiter.forget_idle(Instant::now());
+                    select_biased! {
+                        _ = shutdown.wait().fuse() => (),
+                        _ = wakeup.notified().fuse() => (),
+                    }
+                }
+            }
+        }
+    })
+}
+
+async fn send(
+    bot: &tbot::Bot<Proxy>,
+    db: &Mutex<Database>,
+    limiter: &mut RateLimiter,
+    msg: Outgoing,
+) {
+    let text = if msg.plain {
+        parameters::Text::plain(&msg.text)
+    } else {
+        parameters::Text::html(&msg.text)
+    };
+    let result = bot
+        .send_message(tbot::types::chat::Id(msg.chat), text)
+        .web_page_preview(WebPagePreviewState::Disabled)
+        .call()
+        .await;
+    limiter.sent(msg.chat, Instant::now());
+    let mut db = db.lock().unwrap();
+    match result {
+        Ok(_) => db.sent_outgoing(msg.id),
+        Err(MethodCall::RequestError { description, .. }) if chat_is_unavailable(&description) => {
+            db.delete_subscriber(msg.chat);
+        }
+        Err(MethodCall::RequestError {
+            migrate_to_chat_id: Some(new_chat_id),
+            ..
+        }) => {
+            // The message is retried with the new ID
+            db.update_subscriber(msg.chat, new_chat_id.0);
+        }
+        Err(MethodCall::RequestError { description, .. })
+            if !msg.plain && description.contains("can't parse entities") =>
+        {
+            warn!("bad markup in a message to {}: {}", msg.chat, description);
+            db.fallback_to_plain(msg.id);
+        }
+        Err(MethodCall::RequestError {
+            retry_after: Some(delay),
+            ..
+        }) => {
+            limiter.pause(msg.chat, Instant::now() + Duration::from_secs(delay));
+        }
+        Err(e) if is_transient(&e) && msg.attempts + 1 < MAX_ATTEMPTS => {
+            let delay = RETRY_BACKOFF * 2u32.pow(msg.attempts);
+            db.retry_outgoing(msg.id, SystemTime::now() + delay);
+        }
+        Err(e) => {
+            warn!("giving up a message to {}: {}", msg.chat, e);
+            db.remove_outgoing(msg.id);
+        }
+    }
+    db.save_later();
+}
+
+pub fn chat_is_unavailable(s: &str) -> bool {
+    s.contains("Forbidden") || s.contains("chat not found")
+}
+
+fn is_transient(e: &MethodCall) -> bool {
+    match e {
+        MethodCall::Network(_) | MethodCall::OutOfService | MethodCall::Parse { .. } => true,
+        MethodCall::RequestError { error_code, .. } => *error_code >= 500,
+    }
+}
+
+#[derive(Debug)]
+enum Pick {
+    Send(Outgoing),
+    Wait(Instant),
+    Empty,
+}
+
+/// Spacing of messages, globally and per chat
+struct RateLimiter {
+    next_global: Instant,
+    next_chat: HashMap<i64, Instant>,
+}
+
+impl RateLimiter {
+    fn new() -> Self {
+        RateLimiter {
+            next_global: Instant::now(),
+            next_chat: HashMap::new(),
+        }
+    }
+
+    /// The oldest message which can be sent now, or when to try again
+    ///
+    /// Only the first message of each chat is considered, so the messages
+    /// to a chat are sent in order.
+    fn pick(&self, outbox: &VecDeque<Outgoing>, now: Instant, wall: SystemTime) -> Pick {
+        let mut seen = HashSet::new();
+        let mut earliest: Option<Instant> = None;
+        for msg in outbox {
+            if !seen.insert(msg.chat) {
+                continue;
+            }
+            let not_before = msg
+                .not_before
+                .and_then(|t| t.duration_since(wall).ok())
+                .map(|d| now + d)
+                .unwrap_or(now);
+            let ready = self
+                .next_chat
+                .get(&msg.chat)
+                .map(|&t| cmp::max(t, not_before))
+                .unwrap_or(not_before);
+            if ready <= now && self.next_global <= now {
+                return Pick::Send(msg.clone());
+            }
+            earliest = Some(earliest.map(|t| cmp::min(t, ready)).unwrap_or(ready));
+        }
+        match earliest {
+            Some(t) => Pick::Wait(cmp::max(t, self.next_global)),
+            None => Pick::Empty,
+        }
+    }
+
+    fn sent(&mut self, chat: i64, now: Instant) {
+        // Group and channel IDs are negative
+        let spacing = if chat < 0 {
+            GROUP_SPACING
+        } else {
+            PRIVATE_SPACING
+        };
+        self.next_global = now + GLOBAL_SPACING;
+        self.pause(chat, now + spacing);
+    }
+
+    fn pause(&mut self, chat: i64, until: Instant) {
+        let next = self.next_chat.entry(chat).or_insert(until);
+        *next = cmp::max(*next, until);
+    }
+
+    fn forget_idle(&mut self, now: Instant) {
+        self.next_chat.retain(|_, t| *t > now);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn msg(id: u64, chat: i64) -> Outgoing {
+        Outgoing {
+            id,
+            chat,
+            text: String::new(),
+            plain: false,
+            attempts: 0,
+            not_before: None,
+            items: Vec::new(),
+        }
+    }
+
+    fn picked(pick: Pick) -> u64 {
+        match pick {
+            Pick::Send(msg) => msg.id,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut limiter = RateLimiter::new();
+        let now = Instant::now();
+        let wall = SystemTime::now();
+        let outbox: VecDeque<_> = vec![msg(0, -1), msg(1, -1), msg(2, 2)].into();
+        assert!(matches!(
+            limiter.pick(&VecDeque::new(), now, wall),
+            Pick::Empty
+        ));
+        assert_eq!(picked(limiter.pick(&outbox, now, wall)), 0);
+
+        limiter.sent(-1, now);
+        let outbox: VecDeque<_> = outbox.into_iter().skip(1).collect();
+        match limiter.pick(&outbox, now, wall) {
+            Pick::Wait(t) => assert_eq!(t, now + GLOBAL_SPACING),
+            other => panic!("{:?}", other),
+        }
+        // the group has to wait, the private chat doesn't
+        let later = now + GLOBAL_SPACING;
+        assert_eq!(picked(limiter.pick(&outbox, later, wall)), 2);
+
+        limiter.pause(2, now + Duration::from_secs(60));
+        let outbox: VecDeque<_> = vec![msg(2, 2)].into();
+        match limiter.pick(&outbox, later, wall) {
+            Pick::Wait(t) => assert_eq!(t, now + Duration::from_secs(60)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn retry_later() {
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+        let wall = SystemTime::now();
+        let mut retried = msg(0, 1);
+        retried.not_before = Some(wall + Duration::from_secs(10));
+        let outbox: VecDeque<_> = vec![retried, msg(1, 1), msg(2, 2)].into();
+        // messages to the same chat wait behind it, others don't
+        assert_eq!(picked(limiter.pick(&outbox, now, wall)), 2);
+        let outbox: VecDeque<_> = outbox.into_iter().take(2).collect();
+        match limiter.pick(&outbox, now, wall) {
+            Pick::Wait(t) => assert_eq!(t, now + Duration::from_secs(10)),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use tokio::signal;
+use tokio::sync::watch;
+
+/// Tells the background tasks to stop
+pub struct Trigger(watch::Sender<bool>);
+
+/// Held by the background tasks, to learn when to stop
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+pub fn channel() -> (Trigger, Shutdown) {
+    let (sender, receiver) = watch::channel(false);
+    (Trigger(sender), Shutdown(receiver))
+}
+
+impl Trigger {
+    pub fn fire(self) {
+        let _ = self.0.broadcast(true);
+    }
+}
+
+impl Shutdown {
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once the shutdown is triggered
+    pub async fn wait(&mut self) {
+        while let Some(triggered) = self.0.recv().await {
+            if triggered {
+                return;
+            }
+        }
+    }
+}
+
+/// Wait for SIGTERM or Ctrl-C
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use futures::{future::FutureExt, select_biased};
+        use signal::unix::{signal, SignalKind};
+        use tokio::stream::StreamExt;
+
+        let mut term = signal(SignalKind::terminate()).expect("failed to listen SIGTERM");
+        select_biased! {
+            _ = term.next().fuse() => (),
+            _ = signal::ctrl_c().fuse() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = signal::ctrl_c().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_for_trigger() {
+        let (trigger, mut shutdown) = channel();
+        let mut other = shutdown.clone();
+        assert!(!shutdown.is_triggered());
+        trigger.fire();
+        shutdown.wait().await;
+        other.wait().await;
+        assert!(other.is_triggered());
+    }
+}